@@ -0,0 +1,91 @@
+use sqlx::{Pool, Mssql};
+use sqlx::mssql::MssqlConnection;
+use crate::table::TableRef;
+
+// The applied change tracking version of every table lives on the Replica, next to the data it
+// describes, so a batch and its version commit (or roll back) together. Redis only mirrors it
+// for the dashboard.
+
+const STATE_TABLE: &str = "[dbo].[__ct_sync_state]";
+const GROUPS_TABLE: &str = "[dbo].[__ct_sync_groups]";
+
+/// Creates the version and consistency group watermark tables on the Replica if they do not
+/// exist yet.
+pub async fn ensure_state_table(replica_pool: &Pool<Mssql>) -> Result<(), sqlx::Error> {
+    let create_sql = format!(
+        "IF OBJECT_ID(N'{}', N'U') IS NULL
+         CREATE TABLE {} (
+             [table_name] NVARCHAR(300) NOT NULL PRIMARY KEY,
+             [version] BIGINT NOT NULL,
+             [updated_at] DATETIME2 NOT NULL
+         );
+         IF OBJECT_ID(N'{}', N'U') IS NULL
+         CREATE TABLE {} (
+             [group_name] NVARCHAR(128) NOT NULL PRIMARY KEY,
+             [watermark] BIGINT NOT NULL,
+             [updated_at] DATETIME2 NOT NULL
+         );",
+        STATE_TABLE, STATE_TABLE, GROUPS_TABLE, GROUPS_TABLE
+    );
+    sqlx::query(&create_sql).execute(replica_pool).await?;
+    Ok(())
+}
+
+/// Moves the version recorded under the table's earlier `schema.name` form to its
+/// `[schema].[name]` form, unless one is already recorded there.
+pub async fn migrate_table_name(replica_pool: &Pool<Mssql>, table: &TableRef) -> Result<(), sqlx::Error> {
+    let migrate_sql = format!(
+        "IF NOT EXISTS (SELECT 1 FROM {} WHERE [table_name] = @p1)
+             UPDATE {} SET [table_name] = @p1 WHERE [table_name] = @p2;
+         DELETE FROM {} WHERE [table_name] = @p2;",
+        STATE_TABLE, STATE_TABLE, STATE_TABLE
+    );
+    sqlx::query(&migrate_sql)
+        .bind(table.to_string())
+        .bind(table.dotted())
+        .execute(replica_pool)
+        .await?;
+    Ok(())
+}
+
+/// The version last committed for `table`, or `None` if the Replica has not recorded one.
+pub async fn get_version(replica_pool: &Pool<Mssql>, table: &TableRef) -> Result<Option<i64>, sqlx::Error> {
+    let query = format!("SELECT [version] FROM {} WHERE [table_name] = @p1", STATE_TABLE);
+    sqlx::query_scalar(&query)
+        .bind(table.to_string())
+        .fetch_optional(replica_pool)
+        .await
+}
+
+/// Records `version` for `table` on the given connection, typically inside the transaction that
+/// applied the changes up to it.
+pub async fn set_version(conn: &mut MssqlConnection, table: &TableRef, version: i64) -> Result<(), sqlx::Error> {
+    let upsert_sql = format!(
+        "UPDATE {} SET [version] = @p2, [updated_at] = SYSUTCDATETIME() WHERE [table_name] = @p1;
+         IF @@ROWCOUNT = 0
+             INSERT INTO {} ([table_name], [version], [updated_at]) VALUES (@p1, @p2, SYSUTCDATETIME());",
+        STATE_TABLE, STATE_TABLE
+    );
+    sqlx::query(&upsert_sql)
+        .bind(table.to_string())
+        .bind(version)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Records the version every member of consistency group `group` has reached.
+pub async fn set_group_watermark(replica_pool: &Pool<Mssql>, group: &str, watermark: i64) -> Result<(), sqlx::Error> {
+    let upsert_sql = format!(
+        "UPDATE {} SET [watermark] = @p2, [updated_at] = SYSUTCDATETIME() WHERE [group_name] = @p1;
+         IF @@ROWCOUNT = 0
+             INSERT INTO {} ([group_name], [watermark], [updated_at]) VALUES (@p1, @p2, SYSUTCDATETIME());",
+        GROUPS_TABLE, GROUPS_TABLE
+    );
+    sqlx::query(&upsert_sql)
+        .bind(group)
+        .bind(watermark)
+        .execute(replica_pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use sqlx::Row;
+use sqlx::mssql::MssqlRow;
+
+// Replica columns are declared from the Primary's `sys.columns` / `sys.types` rows, so CREATE
+// TABLE, ADD COLUMN and ALTER COLUMN render exactly the same definition for a column.
+
+/// A column as declared on the Primary, read by `schema::get_column_definitions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDefinition {
+    pub column_id: i32,
+    pub name: String,
+    /// Built-in type name; alias types are resolved to their base type
+    pub type_name: String,
+    /// `sys.columns.max_length` in bytes, -1 for MAX types
+    pub max_length: i32,
+    pub precision: i32,
+    pub scale: i32,
+    pub collation: Option<String>,
+    pub is_nullable: bool,
+    pub is_sparse: bool,
+    pub is_rowguidcol: bool,
+    pub is_filestream: bool,
+    /// `(seed, increment)` of an identity column
+    pub identity: Option<(String, String)>,
+    /// Default constraint definition, e.g. `((0))`
+    pub default: Option<String>,
+}
+
+/// Select list matching `ColumnDefinition::from_row`, for `sys.columns c` joined to
+/// `sys.types t` on `user_type_id`, `sys.types bt` on the base type of alias types,
+/// `sys.identity_columns ic` and `sys.default_constraints dc`.
+pub const DEFINITION_SELECT: &str = "
+    c.column_id AS ColumnId,
+    c.name AS ColumnName,
+    COALESCE(bt.name, t.name) AS TypeName,
+    CAST(c.max_length AS INT) AS MaxLength,
+    CAST(c.precision AS INT) AS NumericPrecision,
+    CAST(c.scale AS INT) AS NumericScale,
+    c.collation_name AS CollationName,
+    c.is_nullable AS IsNullable,
+    c.is_sparse AS IsSparse,
+    c.is_rowguidcol AS IsRowGuidCol,
+    c.is_filestream AS IsFilestream,
+    c.is_identity AS IsIdentity,
+    CAST(ic.seed_value AS VARCHAR(40)) AS IdentSeed,
+    CAST(ic.increment_value AS VARCHAR(40)) AS IdentIncr,
+    CAST(dc.definition AS NVARCHAR(4000)) AS DefaultDefinition";
+
+impl ColumnDefinition {
+    pub fn from_row(row: &MssqlRow) -> Result<Self, sqlx::Error> {
+        let identity = if row.try_get::<bool, _>("IsIdentity")? {
+            // Both values are inlined, so anything but an integer falls back to the default of 1
+            let read = |column: &str| row.try_get::<Option<String>, _>(column).ok().flatten()
+                .filter(|v| v.parse::<i128>().is_ok())
+                .unwrap_or_else(|| "1".to_string());
+            Some((read("IdentSeed"), read("IdentIncr")))
+        } else {
+            None
+        };
+
+        Ok(ColumnDefinition {
+            column_id: row.try_get("ColumnId")?,
+            name: row.try_get("ColumnName")?,
+            type_name: row.try_get::<String, _>("TypeName")?.to_lowercase(),
+            max_length: row.try_get("MaxLength")?,
+            precision: row.try_get("NumericPrecision")?,
+            scale: row.try_get("NumericScale")?,
+            collation: row.try_get("CollationName")?,
+            is_nullable: row.try_get("IsNullable")?,
+            is_sparse: row.try_get("IsSparse")?,
+            is_rowguidcol: row.try_get("IsRowGuidCol")?,
+            is_filestream: row.try_get("IsFilestream")?,
+            identity,
+            default: row.try_get("DefaultDefinition")?,
+        })
+    }
+
+    /// The data type with its length, precision and scale, e.g. `nvarchar(50)`, `decimal(10, 2)`.
+    pub fn render_type(&self) -> String {
+        let length = |bytes: i32| if bytes == -1 { "max".to_string() } else { bytes.to_string() };
+        match self.type_name.as_str() {
+            "char" | "varchar" | "binary" | "varbinary" => {
+                format!("{}({})", self.type_name, length(self.max_length))
+            },
+            // Unicode lengths are declared in byte pairs
+            "nchar" | "nvarchar" => {
+                let chars = if self.max_length == -1 { -1 } else { self.max_length / 2 };
+                format!("{}({})", self.type_name, length(chars))
+            },
+            "decimal" | "numeric" => format!("{}({}, {})", self.type_name, self.precision, self.scale),
+            "datetime2" | "datetimeoffset" | "time" => format!("{}({})", self.type_name, self.scale),
+            // Every other built-in type has a fixed size: float(n) is stored as real or float
+            // itself, and sysname is nvarchar(128)
+            _ => self.type_name.clone(),
+        }
+    }
+
+    /// The full column definition for CREATE TABLE / ADD, e.g.
+    /// `[Name] nvarchar(50) COLLATE Latin1_General_CI_AS NOT NULL DEFAULT ('')`.
+    pub fn render(&self) -> String {
+        let mut definition = format!("[{}] {}", self.name.replace(']', "]]"), self.render_type());
+        if self.is_filestream {
+            definition.push_str(" FILESTREAM");
+        }
+        if let Some(collation) = &self.collation {
+            definition.push_str(&format!(" COLLATE {}", collation));
+        }
+        if self.is_sparse {
+            definition.push_str(" SPARSE");
+        }
+        if let Some((seed, increment)) = &self.identity {
+            definition.push_str(&format!(" IDENTITY({},{})", seed, increment));
+        }
+        definition.push_str(if self.is_nullable { " NULL" } else { " NOT NULL" });
+        if self.is_rowguidcol {
+            definition.push_str(" ROWGUIDCOL");
+        }
+        if let Some(default) = &self.default {
+            definition.push_str(&format!(" DEFAULT {}", default));
+        }
+        definition
+    }
+
+    /// The definition for `ALTER TABLE ... ALTER COLUMN`: type, collation and nullability.
+    pub fn render_alter(&self) -> String {
+        let mut definition = format!("[{}] {}", self.name.replace(']', "]]"), self.render_type());
+        if let Some(collation) = &self.collation {
+            definition.push_str(&format!(" COLLATE {}", collation));
+        }
+        definition.push_str(if self.is_nullable { " NULL" } else { " NOT NULL" });
+        definition
+    }
+
+    /// Whether `existing` needs an ALTER COLUMN to match this definition.
+    pub fn needs_alter(&self, existing: &ColumnDefinition) -> bool {
+        self.render_type() != existing.render_type()
+            || self.collation != existing.collation
+            || self.is_nullable != existing.is_nullable
+    }
+
+    /// Whether altering `existing` to this definition keeps every value it can hold: lengths,
+    /// precision and ranges only grow, and nullability is only relaxed. Anything else (narrowing,
+    /// other type families, a new code page for non-Unicode text) may lose data or fail on
+    /// existing rows.
+    pub fn is_widening_of(&self, existing: &ColumnDefinition) -> bool {
+        if existing.is_nullable && !self.is_nullable {
+            return false;
+        }
+        if self.collation != existing.collation
+            && ["char", "varchar", "text"].contains(&self.type_name.as_str())
+        {
+            return false;
+        }
+
+        // Character and binary lengths in characters / bytes, None for MAX
+        let length = |c: &ColumnDefinition| match (c.max_length, c.type_name.as_str()) {
+            (-1, _) => None,
+            (bytes, "nchar" | "nvarchar") => Some(bytes / 2),
+            (bytes, _) => Some(bytes),
+        };
+        let longer = match (length(self), length(existing)) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(new), Some(old)) => new >= old,
+        };
+        const INTEGERS: [&str; 4] = ["tinyint", "smallint", "int", "bigint"];
+        let rank = |ladder: &[&str], t: &str| ladder.iter().position(|l| *l == t);
+
+        match (existing.type_name.as_str(), self.type_name.as_str()) {
+            (old, new) if old == new => match new {
+                "char" | "varchar" | "nchar" | "nvarchar" | "binary" | "varbinary" => longer,
+                "decimal" | "numeric" => {
+                    self.scale >= existing.scale
+                        && self.precision - self.scale >= existing.precision - existing.scale
+                },
+                "datetime2" | "datetimeoffset" | "time" => self.scale >= existing.scale,
+                _ => true,
+            },
+            // Fixed to variable length, and non-Unicode to Unicode, within the same family
+            ("char", "varchar" | "nchar" | "nvarchar") | ("varchar", "nvarchar") | ("nchar", "nvarchar") => longer,
+            ("binary", "varbinary") => longer,
+            ("real", "float") | ("smallmoney", "money") => true,
+            (old, new) => match (rank(&INTEGERS, old), rank(&INTEGERS, new)) {
+                (Some(old), Some(new)) => new >= old,
+                _ => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(type_name: &str, max_length: i32, precision: i32, scale: i32) -> ColumnDefinition {
+        ColumnDefinition {
+            column_id: 1,
+            name: "Col".to_string(),
+            type_name: type_name.to_string(),
+            max_length,
+            precision,
+            scale,
+            collation: None,
+            is_nullable: true,
+            is_sparse: false,
+            is_rowguidcol: false,
+            is_filestream: false,
+            identity: None,
+            default: None,
+        }
+    }
+
+    #[test]
+    fn renders_fixed_size_types_without_modifiers() {
+        let cases = [
+            ("bit", 1, 1, 0),
+            ("tinyint", 1, 3, 0),
+            ("smallint", 2, 5, 0),
+            ("int", 4, 10, 0),
+            ("bigint", 8, 19, 0),
+            ("real", 4, 24, 0),
+            ("float", 8, 53, 0),
+            ("money", 8, 19, 4),
+            ("smallmoney", 4, 10, 4),
+            ("date", 3, 10, 0),
+            ("datetime", 8, 23, 3),
+            ("smalldatetime", 4, 16, 0),
+            ("uniqueidentifier", 16, 0, 0),
+            ("text", 16, 0, 0),
+            ("ntext", 16, 0, 0),
+            ("image", 16, 0, 0),
+            ("xml", -1, 0, 0),
+            ("sql_variant", 8016, 0, 0),
+            ("timestamp", 8, 0, 0),
+            ("hierarchyid", 892, 0, 0),
+            ("geometry", -1, 0, 0),
+            ("geography", -1, 0, 0),
+            ("sysname", 256, 0, 0),
+        ];
+        for (type_name, max_length, precision, scale) in cases {
+            assert_eq!(column(type_name, max_length, precision, scale).render_type(), type_name);
+        }
+    }
+
+    #[test]
+    fn renders_character_and_binary_lengths() {
+        assert_eq!(column("char", 10, 0, 0).render_type(), "char(10)");
+        assert_eq!(column("varchar", 255, 0, 0).render_type(), "varchar(255)");
+        assert_eq!(column("varchar", -1, 0, 0).render_type(), "varchar(max)");
+        assert_eq!(column("binary", 16, 0, 0).render_type(), "binary(16)");
+        assert_eq!(column("varbinary", 8000, 0, 0).render_type(), "varbinary(8000)");
+        assert_eq!(column("varbinary", -1, 0, 0).render_type(), "varbinary(max)");
+    }
+
+    #[test]
+    fn renders_unicode_lengths_in_characters() {
+        assert_eq!(column("nchar", 20, 0, 0).render_type(), "nchar(10)");
+        assert_eq!(column("nvarchar", 100, 0, 0).render_type(), "nvarchar(50)");
+        assert_eq!(column("nvarchar", 8000, 0, 0).render_type(), "nvarchar(4000)");
+        assert_eq!(column("nvarchar", -1, 0, 0).render_type(), "nvarchar(max)");
+    }
+
+    #[test]
+    fn renders_precision_and_scale() {
+        assert_eq!(column("decimal", 9, 18, 0).render_type(), "decimal(18, 0)");
+        assert_eq!(column("decimal", 17, 38, 10).render_type(), "decimal(38, 10)");
+        assert_eq!(column("numeric", 9, 10, 2).render_type(), "numeric(10, 2)");
+    }
+
+    #[test]
+    fn renders_fractional_second_scale() {
+        assert_eq!(column("datetime2", 8, 27, 7).render_type(), "datetime2(7)");
+        assert_eq!(column("datetime2", 6, 19, 0).render_type(), "datetime2(0)");
+        assert_eq!(column("datetimeoffset", 9, 30, 3).render_type(), "datetimeoffset(3)");
+        assert_eq!(column("time", 4, 12, 3).render_type(), "time(3)");
+    }
+
+    #[test]
+    fn renders_nullability_and_default() {
+        let mut col = column("int", 4, 10, 0);
+        assert_eq!(col.render(), "[Col] int NULL");
+        col.is_nullable = false;
+        col.default = Some("((0))".to_string());
+        assert_eq!(col.render(), "[Col] int NOT NULL DEFAULT ((0))");
+    }
+
+    #[test]
+    fn renders_collation() {
+        let mut col = column("varchar", 10, 0, 0);
+        col.collation = Some("Latin1_General_BIN2".to_string());
+        assert_eq!(col.render(), "[Col] varchar(10) COLLATE Latin1_General_BIN2 NULL");
+    }
+
+    #[test]
+    fn renders_sparse() {
+        let mut col = column("nvarchar", 200, 0, 0);
+        col.is_sparse = true;
+        assert_eq!(col.render(), "[Col] nvarchar(100) SPARSE NULL");
+    }
+
+    #[test]
+    fn renders_rowguidcol() {
+        let mut col = column("uniqueidentifier", 16, 0, 0);
+        col.is_nullable = false;
+        col.is_rowguidcol = true;
+        col.default = Some("(newsequentialid())".to_string());
+        assert_eq!(col.render(), "[Col] uniqueidentifier NOT NULL ROWGUIDCOL DEFAULT (newsequentialid())");
+    }
+
+    #[test]
+    fn renders_filestream() {
+        let mut col = column("varbinary", -1, 0, 0);
+        col.is_filestream = true;
+        assert_eq!(col.render(), "[Col] varbinary(max) FILESTREAM NULL");
+    }
+
+    #[test]
+    fn renders_identity() {
+        let mut col = column("bigint", 8, 19, 0);
+        col.is_nullable = false;
+        col.identity = Some(("1000".to_string(), "5".to_string()));
+        assert_eq!(col.render(), "[Col] bigint IDENTITY(1000,5) NOT NULL");
+    }
+
+    #[test]
+    fn renders_alter_without_column_options() {
+        let mut col = column("varchar", 20, 0, 0);
+        col.collation = Some("Latin1_General_CI_AS".to_string());
+        col.is_nullable = false;
+        col.default = Some("('')".to_string());
+        assert_eq!(col.render_alter(), "[Col] varchar(20) COLLATE Latin1_General_CI_AS NOT NULL");
+    }
+
+    #[test]
+    fn detects_alterations() {
+        let existing = column("varchar", 10, 0, 0);
+        assert!(!existing.needs_alter(&existing));
+        assert!(column("varchar", 20, 0, 0).needs_alter(&existing));
+        let mut not_null = existing.clone();
+        not_null.is_nullable = false;
+        assert!(not_null.needs_alter(&existing));
+        let mut with_default = existing.clone();
+        with_default.default = Some("('')".to_string());
+        assert!(!with_default.needs_alter(&existing));
+    }
+
+    #[test]
+    fn accepts_widening_changes() {
+        assert!(column("varchar", 20, 0, 0).is_widening_of(&column("varchar", 10, 0, 0)));
+        assert!(column("varchar", -1, 0, 0).is_widening_of(&column("varchar", 8000, 0, 0)));
+        assert!(column("nvarchar", 20, 0, 0).is_widening_of(&column("varchar", 10, 0, 0)));
+        assert!(column("varbinary", 16, 0, 0).is_widening_of(&column("binary", 16, 0, 0)));
+        assert!(column("decimal", 13, 12, 3).is_widening_of(&column("decimal", 9, 10, 2)));
+        assert!(column("datetime2", 8, 27, 7).is_widening_of(&column("datetime2", 6, 19, 0)));
+        assert!(column("bigint", 8, 19, 0).is_widening_of(&column("int", 4, 10, 0)));
+        assert!(column("float", 8, 53, 0).is_widening_of(&column("real", 4, 24, 0)));
+
+        let mut nullable = column("int", 4, 10, 0);
+        let mut not_null = nullable.clone();
+        not_null.is_nullable = false;
+        assert!(nullable.is_widening_of(&not_null));
+        nullable.is_nullable = false;
+        assert!(nullable.is_widening_of(&not_null));
+    }
+
+    #[test]
+    fn refuses_narrowing_changes() {
+        assert!(!column("varchar", 10, 0, 0).is_widening_of(&column("varchar", 20, 0, 0)));
+        assert!(!column("varchar", 8000, 0, 0).is_widening_of(&column("varchar", -1, 0, 0)));
+        assert!(!column("varchar", 20, 0, 0).is_widening_of(&column("nvarchar", 20, 0, 0)));
+        assert!(!column("decimal", 9, 10, 4).is_widening_of(&column("decimal", 9, 10, 2)));
+        assert!(!column("decimal", 9, 10, 1).is_widening_of(&column("decimal", 9, 10, 2)));
+        assert!(!column("time", 3, 8, 0).is_widening_of(&column("time", 5, 16, 7)));
+        assert!(!column("int", 4, 10, 0).is_widening_of(&column("bigint", 8, 19, 0)));
+        assert!(!column("int", 4, 10, 0).is_widening_of(&column("varchar", 10, 0, 0)));
+
+        let mut not_null = column("int", 4, 10, 0);
+        not_null.is_nullable = false;
+        assert!(!not_null.is_widening_of(&column("int", 4, 10, 0)));
+
+        let mut recollated = column("varchar", 10, 0, 0);
+        recollated.collation = Some("Cyrillic_General_CI_AS".to_string());
+        let mut existing = column("varchar", 10, 0, 0);
+        existing.collation = Some("Latin1_General_CI_AS".to_string());
+        assert!(!recollated.is_widening_of(&existing));
+    }
+
+    #[test]
+    fn escapes_column_name() {
+        let mut col = column("int", 4, 10, 0);
+        col.name = "Odd]Name".to_string();
+        assert_eq!(col.render(), "[Odd]]Name] int NULL");
+    }
+}
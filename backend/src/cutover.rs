@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use sqlx::{Pool, Mssql, Row};
+use sqlx::mssql::MssqlConnection;
+use log::{info, warn, error};
+use redis::Client;
+use tokio_util::sync::CancellationToken;
+use crate::checkpoint;
+use crate::foreign_keys;
+use crate::schema;
+use crate::snapshot;
+use crate::state;
+use crate::sync::{self, SyncOptions};
+use crate::table::TableRef;
+
+// Planned cutover (`backend cutover`): verifies that writes on the Primary have stopped, drains
+// the remaining changes of every enabled table, validates the Replica against the Primary and
+// prepares it to take over writes. The outcome is logged and stored in Redis.
+
+/// Name under which the drain syncs all tables as one consistency group.
+const CUTOVER_GROUP: &str = "cutover";
+
+/// Outcome of a cutover, stored in `mssql_sync:cutover_report`.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CutoverReport {
+    /// Change tracking version the Replica was drained to
+    pub final_version: i64,
+    /// Whether the Primary saw no writes from the quiet period to the end of the cutover
+    pub quiesced: bool,
+    pub tables: Vec<TableReport>,
+    /// Tables on the Primary that are not enabled for sync or not change-tracked, so they were not
+    /// drained. They fail the cutover unless `CUTOVER_ALLOW_SKIPPED_TABLES=true`.
+    pub skipped_tables: Vec<String>,
+    pub safe_to_promote: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableReport {
+    pub table: String,
+    pub version: i64,
+    pub primary_rows: i64,
+    pub replica_rows: i64,
+    pub checksum_match: bool,
+    pub constraints_valid: bool,
+    pub errors: Vec<String>,
+}
+
+impl TableReport {
+    fn ok(&self, final_version: i64) -> bool {
+        self.errors.is_empty()
+            && self.version >= final_version
+            && self.primary_rows == self.replica_rows
+            && self.checksum_match
+            && self.constraints_valid
+    }
+}
+
+/// Runs the cutover and reports whether the Replica is safe to promote. Writes on the Primary
+/// must be stopped beforehand; the cutover only verifies that they have been, by watching the
+/// Primary's writes (see `write_marker`) for `CUTOVER_QUIET_SECS` (default 10).
+pub async fn run(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    options: &SyncOptions,
+) -> Result<CutoverReport, Box<dyn std::error::Error>> {
+    let quiet_secs = std::env::var("CUTOVER_QUIET_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(10);
+    let allow_skipped = std::env::var("CUTOVER_ALLOW_SKIPPED_TABLES")
+        .map(|v| v.to_lowercase() == "true")
+        .unwrap_or(false);
+
+    // Every user table counts, so tables with a primary key but without change tracking, which
+    // are never replicated, show up as skipped too
+    let source_tables = schema::list_source_tables(primary_pool).await?;
+    let mut tables = Vec::new();
+    let mut skipped_tables = Vec::new();
+    for table in schema::list_user_tables(primary_pool).await? {
+        if source_tables.contains(&table) && state::is_table_enabled(redis_client, &table.to_string()).await.unwrap_or(false) {
+            tables.push(table);
+        } else {
+            skipped_tables.push(table.to_string());
+        }
+    }
+    let dependencies = foreign_keys::get_dependencies(primary_pool).await?;
+    let tables = foreign_keys::dependency_order(tables, &dependencies);
+
+    // 1. Verify that writes on the Primary are quiesced
+    let mut primary = primary_pool.acquire().await?;
+    let start = write_marker(&mut primary).await?;
+    info!("Cutover: watching the Primary for writes for {}s (version {})...", quiet_secs, start.version);
+    tokio::time::sleep(Duration::from_secs(quiet_secs)).await;
+    let marker = write_marker(&mut primary).await?;
+    let final_version = marker.version;
+    if marker != start {
+        error!(
+            "Cutover: the Primary is still being written to (v{} -> v{}, {} -> {} table writes); stop all writers first",
+            start.version, marker.version, start.user_updates, marker.user_updates
+        );
+        let report = CutoverReport { final_version, quiesced: false, tables: Vec::new(), skipped_tables, safe_to_promote: false };
+        return finish(redis_client, report).await;
+    }
+
+    // 2. Drain the remaining changes of every table to the final version. Snapshot diffs of
+    // tables without change tracking are made due so they run now.
+    for table in &tables {
+        state::clear_snapshot_diff_at(redis_client, &table.to_string()).await?;
+    }
+    info!("Cutover: draining {} tables to v{}...", tables.len(), final_version);
+    let drain_error = sync::run_group_sync(primary_pool, replica_pool, redis_client, CUTOVER_GROUP, &tables, options, CancellationToken::new())
+        .await
+        .err()
+        .map(|e| e.to_string());
+    if let Some(e) = &drain_error {
+        error!("Cutover: drain failed: {}", e);
+    }
+
+    // Foreign keys disabled for the drain are checked again before the constraints below
+    if let Err(e) = foreign_keys::revalidate(replica_pool, redis_client, &HashSet::new()).await {
+        warn!("Cutover: foreign key re-validation error: {}", e);
+    }
+
+    let mut reports = Vec::with_capacity(tables.len());
+    for table in &tables {
+        let mut report = TableReport {
+            table: table.to_string(),
+            version: 0,
+            primary_rows: 0,
+            replica_rows: 0,
+            checksum_match: false,
+            constraints_valid: false,
+            errors: Vec::new(),
+        };
+        if let Some(e) = &drain_error {
+            report.errors.push(format!("Drain failed: {}", e));
+        }
+        match checkpoint::get_version(replica_pool, table).await {
+            Ok(version) => report.version = version.unwrap_or(0),
+            Err(e) => report.errors.push(format!("Version: {}", e)),
+        }
+
+        // 3. Row count and checksum validation
+        if let Err(e) = validate_rows(&mut primary, replica_pool, table, &mut report).await {
+            report.errors.push(format!("Validation: {}", e));
+        }
+
+        // 4. Reseed identities so the Replica continues the Primary's sequence
+        if let Err(e) = schema::reseed_identity(&mut primary, replica_pool, table).await {
+            report.errors.push(format!("Identity reseed: {}", e));
+        }
+
+        // 5. Enable Replica triggers and re-validate every constraint so they are trusted again
+        let enable_sql = format!(
+            "ENABLE TRIGGER ALL ON {}; ALTER TABLE {} WITH CHECK CHECK CONSTRAINT ALL;",
+            table.quoted(), table.quoted()
+        );
+        match sqlx::query(&enable_sql).execute(replica_pool).await {
+            Ok(_) => report.constraints_valid = true,
+            Err(e) => report.errors.push(format!("Constraints: {}", e)),
+        }
+
+        if report.ok(final_version) {
+            info!("Cutover: {} OK at v{} ({} rows)", report.table, report.version, report.replica_rows);
+        } else {
+            error!(
+                "Cutover: {} FAILED at v{} (rows {}/{}, checksum {}, constraints {}): {}",
+                report.table, report.version, report.replica_rows, report.primary_rows,
+                report.checksum_match, report.constraints_valid, report.errors.join("; ")
+            );
+        }
+        reports.push(report);
+    }
+
+    // 6. Writes that slipped in during the cutover invalidate the validation above
+    let quiesced = write_marker(&mut primary).await? == marker;
+    if !quiesced {
+        error!("Cutover: the Primary was written to during the cutover");
+    }
+    for table in &skipped_tables {
+        if allow_skipped {
+            warn!("Cutover: {} is not enabled for sync and was not replicated", table);
+        } else {
+            error!("Cutover: {} is not enabled for sync and was not replicated (set CUTOVER_ALLOW_SKIPPED_TABLES=true to accept)", table);
+        }
+    }
+
+    let safe_to_promote = quiesced
+        && (allow_skipped || skipped_tables.is_empty())
+        && reports.iter().all(|r| r.ok(final_version));
+    let report = CutoverReport { final_version, quiesced, tables: reports, skipped_tables, safe_to_promote };
+    finish(redis_client, report).await
+}
+
+/// Stores the report and logs the verdict.
+async fn finish(redis_client: &Client, report: CutoverReport) -> Result<CutoverReport, Box<dyn std::error::Error>> {
+    state::set_cutover_report(redis_client, &serde_json::to_string(&report)?).await?;
+    if report.safe_to_promote {
+        info!("Cutover complete at v{}: the Replica is SAFE to promote", report.final_version);
+    } else {
+        error!("Cutover at v{}: the Replica is NOT safe to promote (report in mssql_sync:cutover_report)", report.final_version);
+    }
+    Ok(report)
+}
+
+/// Write activity on the Primary: the change tracking version only moves for tracked tables, so
+/// the number of user writes to every table since the server started is watched as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WriteMarker {
+    version: i64,
+    user_updates: i64,
+}
+
+/// Reads the current `WriteMarker`. `sys.dm_db_index_usage_stats` needs VIEW SERVER STATE (VIEW
+/// DATABASE STATE on Azure SQL); without it the cutover fails rather than missing writes.
+async fn write_marker(primary: &mut MssqlConnection) -> Result<WriteMarker, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT CHANGE_TRACKING_CURRENT_VERSION() AS Version,
+                CAST(ISNULL((
+                    SELECT SUM(s.user_updates)
+                    FROM sys.dm_db_index_usage_stats AS s
+                    JOIN sys.tables AS t ON t.object_id = s.object_id
+                    WHERE s.database_id = DB_ID() AND t.is_ms_shipped = 0
+                ), 0) AS BIGINT) AS UserUpdates"
+    )
+        .fetch_one(&mut *primary)
+        .await?;
+    Ok(WriteMarker {
+        version: row.try_get::<Option<i64>, _>("Version")?.unwrap_or(0),
+        user_updates: row.try_get("UserUpdates")?,
+    })
+}
+
+/// Compares the row count and the sum of every row's hash between Primary and Replica. Unlike
+/// CHECKSUM_AGG, which XORs its inputs, a sum is not cancelled out by duplicate rows. The sum is
+/// taken over the first 8 bytes of each SHA-256 hash, as DECIMAL so it cannot overflow.
+async fn validate_rows(
+    primary: &mut MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    report: &mut TableReport,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let columns = sync::load_columns(&mut *primary, table).await?;
+    let all_columns: Vec<_> = columns.iter().collect();
+    let validation_query = format!(
+        "SELECT CAST(COUNT_BIG(*) AS BIGINT) AS RowCnt,
+                CAST(SUM(CAST(CAST(CONVERT(BINARY(8), LEFT(h, 16), 2) AS BIGINT) AS DECIMAL(38, 0))) AS VARCHAR(40)) AS RowChecksum
+         FROM (SELECT {} AS h FROM {} AS t) AS r",
+        snapshot::row_hash_expr(&all_columns), table.quoted()
+    );
+
+    let p_row = sqlx::query(&validation_query).fetch_one(&mut *primary).await?;
+    let r_row = sqlx::query(&validation_query).fetch_one(replica_pool).await?;
+    report.primary_rows = p_row.try_get("RowCnt")?;
+    report.replica_rows = r_row.try_get("RowCnt")?;
+    report.checksum_match = p_row.try_get::<Option<String>, _>("RowChecksum")? == r_row.try_get::<Option<String>, _>("RowChecksum")?;
+    Ok(())
+}
@@ -4,6 +4,7 @@ use log::{info, error, warn};
 use redis::Client;
 use tokio::time::sleep;
 use crate::state;
+use crate::table::TableRef;
 
 use tokio_util::sync::CancellationToken;
 
@@ -76,6 +77,14 @@ async fn consume_events(
                         obj_name = &msg_body[targ_start + 18..targ_end];
                     }
 
+                    // The owning schema is reported in <SchemaName> for both plain and target objects
+                    let mut schema_name = "dbo";
+                    if let (Some(sch_start), Some(sch_end)) = (msg_body.find("<SchemaName>"), msg_body.find("</SchemaName>")) {
+                        schema_name = &msg_body[sch_start + 12..sch_end];
+                    }
+                    let table = TableRef::new(schema_name, obj_name);
+                    let obj_name = &table.to_string();
+
                     // Quick decode XML entities for TSQL cmd
                     cmd = cmd.replace("&amp;", "&").replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'");
 
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use sqlx::{Pool, Mssql, Row};
+use log::{info, warn};
+use redis::Client;
+use crate::state::{self, NocheckForeignKey};
+use crate::table::TableRef;
+
+// Tables are dispatched parents first, so rows inserted into a child table usually find their
+// parent rows already on the Replica. A consistency group also applies its deletes children
+// first. Other tables still sync concurrently, so REPLICA_FK_MODE=nocheck can additionally switch
+// the Replica's foreign keys to NOCHECK while their tables are applied and re-validate them once
+// neither side is syncing.
+
+/// Whether the Replica's foreign keys are enforced while changes are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForeignKeyMode {
+    /// Foreign keys stay enabled; a change applied before its parent row fails and is retried
+    Enforce,
+    /// Foreign keys touching a table are disabled when a pass writes to it and re-validated afterwards
+    NoCheck,
+}
+
+impl ForeignKeyMode {
+    /// `REPLICA_FK_MODE` is `enforce` (default) or `nocheck`.
+    pub fn from_env() -> Self {
+        match std::env::var("REPLICA_FK_MODE").as_deref() {
+            Ok("nocheck") => ForeignKeyMode::NoCheck,
+            Ok("enforce") | Err(_) => ForeignKeyMode::Enforce,
+            Ok(other) => {
+                warn!("Unknown REPLICA_FK_MODE '{}'; using enforce", other);
+                ForeignKeyMode::Enforce
+            }
+        }
+    }
+}
+
+/// `(child, parent)` pairs for every foreign key on the Primary between two different tables.
+pub async fn get_dependencies(primary_pool: &Pool<Mssql>) -> Result<Vec<(TableRef, TableRef)>, sqlx::Error> {
+    let dependency_query = "
+        SELECT DISTINCT
+            OBJECT_SCHEMA_NAME(fk.parent_object_id) AS ChildSchema,
+            OBJECT_NAME(fk.parent_object_id) AS ChildName,
+            OBJECT_SCHEMA_NAME(fk.referenced_object_id) AS ParentSchema,
+            OBJECT_NAME(fk.referenced_object_id) AS ParentName
+        FROM sys.foreign_keys fk
+        WHERE fk.parent_object_id <> fk.referenced_object_id
+    ";
+    let rows = sqlx::query(dependency_query).fetch_all(primary_pool).await?;
+    Ok(rows.iter()
+        .map(|row| (
+            TableRef::new(&row.get::<String, _>("ChildSchema"), &row.get::<String, _>("ChildName")),
+            TableRef::new(&row.get::<String, _>("ParentSchema"), &row.get::<String, _>("ParentName")),
+        ))
+        .collect())
+}
+
+/// Orders `tables` so every table comes after the tables it references. Otherwise the original
+/// order is kept; a reference cycle is broken at its first table in that order, and tables that
+/// merely depend on a cycle still follow it.
+pub fn dependency_order(tables: Vec<TableRef>, dependencies: &[(TableRef, TableRef)]) -> Vec<TableRef> {
+    let mut remaining = tables;
+    let mut ordered = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let parents = |table: &TableRef| -> Vec<&TableRef> {
+            dependencies.iter()
+                .filter(|(child, parent)| child == table && remaining.contains(parent))
+                .map(|(_, parent)| parent)
+                .collect()
+        };
+        let next = remaining.iter().position(|table| parents(table).is_empty())
+            .or_else(|| remaining.iter().position(|table| {
+                // Part of a cycle: the table references itself through the remaining tables
+                let mut seen: Vec<&TableRef> = Vec::new();
+                let mut pending = parents(table);
+                while let Some(parent) = pending.pop() {
+                    if parent == table {
+                        return true;
+                    }
+                    if !seen.contains(&parent) {
+                        seen.push(parent);
+                        pending.extend(parents(parent));
+                    }
+                }
+                false
+            }))
+            .unwrap_or(0);
+        ordered.push(remaining.remove(next));
+    }
+    ordered
+}
+
+/// Orders `tables` so every table comes before the tables it references, the order in which a
+/// consistency group applies its deletes.
+pub fn delete_order(tables: &[TableRef], dependencies: &[(TableRef, TableRef)]) -> Vec<TableRef> {
+    let mut ordered = dependency_order(tables.to_vec(), dependencies);
+    ordered.reverse();
+    ordered
+}
+
+/// Switches every enabled Replica foreign key referencing or referenced by `tables` to NOCHECK,
+/// recording it in Redis so `revalidate` turns it back on even after a restart.
+pub async fn disable(
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    tables: &[TableRef],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let object_ids = tables.iter().map(|t| t.object_id()).collect::<Vec<_>>().join(", ");
+    let fk_query = format!(
+        "SELECT
+            fk.name AS ForeignKeyName,
+            OBJECT_SCHEMA_NAME(fk.parent_object_id) AS TableSchema,
+            OBJECT_NAME(fk.parent_object_id) AS TableName,
+            OBJECT_SCHEMA_NAME(fk.referenced_object_id) AS ReferencedSchema,
+            OBJECT_NAME(fk.referenced_object_id) AS ReferencedName
+        FROM sys.foreign_keys fk
+        WHERE fk.is_disabled = 0
+        AND (fk.parent_object_id IN ({}) OR fk.referenced_object_id IN ({}))",
+        object_ids, object_ids
+    );
+
+    for row in sqlx::query(&fk_query).fetch_all(replica_pool).await? {
+        let fk = NocheckForeignKey {
+            name: row.get("ForeignKeyName"),
+            table: TableRef::new(&row.get::<String, _>("TableSchema"), &row.get::<String, _>("TableName")),
+            referenced_table: TableRef::new(&row.get::<String, _>("ReferencedSchema"), &row.get::<String, _>("ReferencedName")),
+        };
+        state::add_nocheck_foreign_key(redis_client, &fk).await?;
+
+        let nocheck_sql = format!("ALTER TABLE {} NOCHECK CONSTRAINT [{}]", fk.table.quoted(), fk.name.replace(']', "]]"));
+        sqlx::query(&nocheck_sql).execute(replica_pool).await?;
+    }
+    Ok(())
+}
+
+/// Re-enables the foreign keys disabled by `disable` whose tables are not in `active`, checking
+/// the existing rows so the constraints are trusted again. Keys whose rows do not validate yet
+/// (e.g. the parent table is behind) stay disabled until a later pass.
+pub async fn revalidate(
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    active: &HashSet<TableRef>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for fk in state::get_nocheck_foreign_keys(redis_client).await? {
+        if active.contains(&fk.table) || active.contains(&fk.referenced_table) {
+            continue;
+        }
+
+        let check_sql = format!(
+            "IF EXISTS (SELECT 1 FROM sys.foreign_keys WHERE name = N'{}' AND parent_object_id = {})
+             ALTER TABLE {} WITH CHECK CHECK CONSTRAINT [{}]",
+            fk.name.replace('\'', "''"), fk.table.object_id(), fk.table.quoted(), fk.name.replace(']', "]]")
+        );
+        match sqlx::query(&check_sql).execute(replica_pool).await {
+            Ok(_) => {
+                info!("Re-validated foreign key {} on {}", fk.name, fk.table);
+                state::remove_nocheck_foreign_key(redis_client, &fk).await?;
+            },
+            Err(e) => warn!("Foreign key {} on {} does not validate yet: {}", fk.name, fk.table, e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(name: &str) -> TableRef {
+        TableRef::new("dbo", name)
+    }
+
+    #[test]
+    fn orders_parents_before_children() {
+        // OrderLines -> Orders -> Customers, Products independent
+        let dependencies = vec![(t("OrderLines"), t("Orders")), (t("Orders"), t("Customers"))];
+        let tables = vec![t("OrderLines"), t("Products"), t("Orders"), t("Customers")];
+
+        assert_eq!(
+            dependency_order(tables, &dependencies),
+            vec![t("Products"), t("Customers"), t("Orders"), t("OrderLines")]
+        );
+    }
+
+    #[test]
+    fn deletes_children_before_their_parents() {
+        // A parent row and the child rows referencing it are deleted in the same group pass
+        let dependencies = vec![(t("OrderLines"), t("Orders"))];
+        let members = dependency_order(vec![t("OrderLines"), t("Orders")], &dependencies);
+
+        assert_eq!(members, vec![t("Orders"), t("OrderLines")]);
+        assert_eq!(delete_order(&members, &dependencies), vec![t("OrderLines"), t("Orders")]);
+    }
+
+    #[test]
+    fn ignores_dependencies_on_tables_outside_the_list() {
+        let dependencies = vec![(t("Orders"), t("Customers"))];
+        assert_eq!(dependency_order(vec![t("Orders"), t("Audit")], &dependencies), vec![t("Orders"), t("Audit")]);
+    }
+
+    #[test]
+    fn orders_self_contained_cycles_by_the_original_order() {
+        let dependencies = vec![(t("A"), t("B")), (t("B"), t("C")), (t("C"), t("A"))];
+        assert_eq!(dependency_order(vec![t("B"), t("C"), t("A")], &dependencies), vec![t("B"), t("A"), t("C")]);
+    }
+
+    #[test]
+    fn breaks_cycles_without_moving_their_dependents_ahead() {
+        // A <-> B form a cycle; C depends on A
+        let dependencies = vec![(t("A"), t("B")), (t("B"), t("A")), (t("C"), t("A"))];
+        let ordered = dependency_order(vec![t("C"), t("A"), t("B")], &dependencies);
+
+        // The cycle is broken at A, its first table in the original order, and C still follows A
+        assert_eq!(ordered, vec![t("A"), t("C"), t("B")]);
+    }
+}
@@ -0,0 +1,578 @@
+use sqlx::Row;
+use sqlx::mssql::MssqlRow;
+use crate::table::TableRef;
+
+// Replica indexes are scripted from the Primary's `sys.indexes`, `sys.index_columns`,
+// `sys.partitions`, `sys.xml_indexes` and `sys.spatial_index_tessellations` rows, so they keep
+// the Primary's structure and options and the Replica gets the same query plans.
+
+/// `sys.indexes.type` values.
+const CLUSTERED: i32 = 1;
+const NONCLUSTERED: i32 = 2;
+const XML: i32 = 3;
+const SPATIAL: i32 = 4;
+const CLUSTERED_COLUMNSTORE: i32 = 5;
+const NONCLUSTERED_COLUMNSTORE: i32 = 6;
+
+/// An index, unique constraint or primary key as declared on the Primary, read by
+/// `schema::get_index_definitions`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDefinition {
+    pub name: String,
+    /// `sys.indexes.type`
+    pub index_type: i32,
+    pub is_primary_key: bool,
+    pub is_unique: bool,
+    pub is_unique_constraint: bool,
+    pub is_disabled: bool,
+    /// Bracketed key columns in key order, with ` DESC` where descending
+    pub key_columns: Option<String>,
+    /// Bracketed `INCLUDE` columns, or the columns of a nonclustered columnstore index
+    pub included_columns: Option<String>,
+    pub filter: Option<String>,
+    pub fill_factor: i32,
+    pub is_padded: bool,
+    pub ignore_dup_key: bool,
+    pub allow_row_locks: bool,
+    pub allow_page_locks: bool,
+    /// `data_compression_desc` of the first partition
+    pub compression: Option<String>,
+    pub xml: Option<XmlIndex>,
+    pub spatial: Option<SpatialIndex>,
+}
+
+/// Secondary XML indexes reference the primary XML index they are built on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlIndex {
+    /// `(primary XML index name, PATH | VALUE | PROPERTY)` for a secondary XML index
+    pub secondary: Option<(String, String)>,
+    /// Selective XML indexes need promoted paths, which are not scripted
+    pub is_selective: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpatialIndex {
+    /// `GEOMETRY_GRID`, `GEOMETRY_AUTO_GRID`, `GEOGRAPHY_GRID` or `GEOGRAPHY_AUTO_GRID`
+    pub tessellation_scheme: String,
+    /// `(xmin, ymin, xmax, ymax)`, geometry only
+    pub bounding_box: Option<(f64, f64, f64, f64)>,
+    /// Densities of the four grid levels, manual grids only
+    pub grids: Option<[String; 4]>,
+    pub cells_per_object: Option<i32>,
+}
+
+/// Select list matching `IndexDefinition::from_row`, for `sys.indexes i` joined to
+/// `sys.xml_indexes xi` and `sys.spatial_index_tessellations st` on the index.
+pub const DEFINITION_SELECT: &str = "
+    i.name AS IndexName,
+    CAST(i.type AS INT) AS IndexType,
+    i.is_primary_key AS IsPrimaryKey,
+    i.is_unique AS IsUnique,
+    i.is_unique_constraint AS IsUniqueConstraint,
+    i.is_disabled AS IsDisabled,
+    CAST(STUFF((
+        SELECT ', ' + QUOTENAME(c.name) + CASE WHEN ic.is_descending_key = 1 THEN ' DESC' ELSE '' END
+        FROM sys.index_columns ic
+        JOIN sys.columns c ON ic.object_id = c.object_id AND ic.column_id = c.column_id
+        WHERE ic.object_id = i.object_id AND ic.index_id = i.index_id AND ic.key_ordinal > 0
+        ORDER BY ic.key_ordinal
+        FOR XML PATH(''), TYPE
+    ).value('.', 'NVARCHAR(MAX)'), 1, 2, '') AS NVARCHAR(4000)) AS KeyColumns,
+    CAST(STUFF((
+        SELECT ', ' + QUOTENAME(c.name)
+        FROM sys.index_columns ic
+        JOIN sys.columns c ON ic.object_id = c.object_id AND ic.column_id = c.column_id
+        WHERE ic.object_id = i.object_id AND ic.index_id = i.index_id AND ic.is_included_column = 1
+        ORDER BY ic.index_column_id
+        FOR XML PATH(''), TYPE
+    ).value('.', 'NVARCHAR(MAX)'), 1, 2, '') AS NVARCHAR(4000)) AS IncludedColumns,
+    CAST(i.filter_definition AS NVARCHAR(4000)) AS FilterDefinition,
+    CAST(i.fill_factor AS INT) AS IndexFillFactor,
+    i.is_padded AS IsPadded,
+    i.ignore_dup_key AS IgnoreDupKey,
+    i.allow_row_locks AS AllowRowLocks,
+    i.allow_page_locks AS AllowPageLocks,
+    (SELECT TOP 1 p.data_compression_desc FROM sys.partitions p
+     WHERE p.object_id = i.object_id AND p.index_id = i.index_id
+     ORDER BY p.partition_number) AS DataCompression,
+    CAST(xi.xml_index_type AS INT) AS XmlIndexType,
+    xi.secondary_type_desc AS XmlSecondaryType,
+    (SELECT ux.name FROM sys.indexes ux
+     WHERE ux.object_id = i.object_id AND ux.index_id = xi.using_xml_index_id) AS UsingXmlIndex,
+    st.tessellation_scheme AS TessellationScheme,
+    st.bounding_box_xmin AS BoundingBoxXmin,
+    st.bounding_box_ymin AS BoundingBoxYmin,
+    st.bounding_box_xmax AS BoundingBoxXmax,
+    st.bounding_box_ymax AS BoundingBoxYmax,
+    st.level_1_grid_desc AS Level1Grid,
+    st.level_2_grid_desc AS Level2Grid,
+    st.level_3_grid_desc AS Level3Grid,
+    st.level_4_grid_desc AS Level4Grid,
+    st.cells_per_object AS CellsPerObject";
+
+impl IndexDefinition {
+    pub fn from_row(row: &MssqlRow) -> Result<Self, sqlx::Error> {
+        let index_type: i32 = row.try_get("IndexType")?;
+
+        let xml = if index_type == XML {
+            let xml_type: Option<i32> = row.try_get("XmlIndexType")?;
+            let using: Option<String> = row.try_get("UsingXmlIndex")?;
+            let secondary_type: Option<String> = row.try_get("XmlSecondaryType")?;
+            Some(XmlIndex {
+                secondary: using.zip(secondary_type),
+                is_selective: xml_type.is_some_and(|t| t > 1),
+            })
+        } else {
+            None
+        };
+
+        let spatial = if index_type == SPATIAL {
+            let bounding_box = match (
+                row.try_get::<Option<f64>, _>("BoundingBoxXmin")?,
+                row.try_get::<Option<f64>, _>("BoundingBoxYmin")?,
+                row.try_get::<Option<f64>, _>("BoundingBoxXmax")?,
+                row.try_get::<Option<f64>, _>("BoundingBoxYmax")?,
+            ) {
+                (Some(xmin), Some(ymin), Some(xmax), Some(ymax)) => Some((xmin, ymin, xmax, ymax)),
+                _ => None,
+            };
+            let grids = match (
+                row.try_get::<Option<String>, _>("Level1Grid")?,
+                row.try_get::<Option<String>, _>("Level2Grid")?,
+                row.try_get::<Option<String>, _>("Level3Grid")?,
+                row.try_get::<Option<String>, _>("Level4Grid")?,
+            ) {
+                (Some(l1), Some(l2), Some(l3), Some(l4)) => Some([l1, l2, l3, l4]),
+                _ => None,
+            };
+            // Bounding boxes only apply to geometry, grid densities only to manual grids
+            let tessellation_scheme = row.try_get::<Option<String>, _>("TessellationScheme")?.unwrap_or_default();
+            Some(SpatialIndex {
+                bounding_box: bounding_box.filter(|_| tessellation_scheme.starts_with("GEOMETRY")),
+                grids: grids.filter(|_| !tessellation_scheme.ends_with("AUTO_GRID")),
+                tessellation_scheme,
+                cells_per_object: row.try_get("CellsPerObject")?,
+            })
+        } else {
+            None
+        };
+
+        Ok(IndexDefinition {
+            name: row.try_get("IndexName")?,
+            index_type,
+            is_primary_key: row.try_get("IsPrimaryKey")?,
+            is_unique: row.try_get("IsUnique")?,
+            is_unique_constraint: row.try_get("IsUniqueConstraint")?,
+            is_disabled: row.try_get("IsDisabled")?,
+            key_columns: row.try_get("KeyColumns")?,
+            included_columns: row.try_get("IncludedColumns")?,
+            filter: row.try_get("FilterDefinition")?,
+            fill_factor: row.try_get("IndexFillFactor")?,
+            is_padded: row.try_get("IsPadded")?,
+            ignore_dup_key: row.try_get("IgnoreDupKey")?,
+            allow_row_locks: row.try_get("AllowRowLocks")?,
+            allow_page_locks: row.try_get("AllowPageLocks")?,
+            compression: row.try_get("DataCompression")?,
+            xml,
+            spatial,
+        })
+    }
+
+    /// Whether the index can be scripted. Hash and memory-optimized indexes, selective XML
+    /// indexes and indexes without readable columns cannot.
+    pub fn is_supported(&self) -> bool {
+        match self.index_type {
+            CLUSTERED | NONCLUSTERED | SPATIAL => self.key_columns.is_some(),
+            XML => self.key_columns.is_some() && !self.xml.as_ref().is_some_and(|x| x.is_selective),
+            CLUSTERED_COLUMNSTORE => true,
+            NONCLUSTERED_COLUMNSTORE => self.included_columns.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Whether `other` has the same type, columns, filter and options, whatever its name.
+    pub fn same_definition(&self, other: &IndexDefinition) -> bool {
+        IndexDefinition { name: other.name.clone(), ..self.clone() } == *other
+    }
+
+    /// Order in which indexes are created: clustered indexes first, so nonclustered ones are
+    /// not rebuilt, and primary XML indexes before the secondary ones built on them.
+    pub fn creation_order(&self) -> u8 {
+        match self.index_type {
+            CLUSTERED | CLUSTERED_COLUMNSTORE => 0,
+            NONCLUSTERED | NONCLUSTERED_COLUMNSTORE => 1,
+            XML if self.xml.as_ref().is_some_and(|x| x.secondary.is_some()) => 3,
+            XML => 2,
+            _ => 4,
+        }
+    }
+
+    fn clustering(&self) -> &'static str {
+        if self.index_type == CLUSTERED { "CLUSTERED" } else { "NONCLUSTERED" }
+    }
+
+    /// The primary key clause for CREATE TABLE, e.g. `PRIMARY KEY NONCLUSTERED ([Id] DESC)`.
+    pub fn primary_key_clause(&self) -> String {
+        format!(
+            "PRIMARY KEY {} ({}){}",
+            self.clustering(), self.key_columns.as_deref().unwrap_or_default(), self.with_options()
+        )
+    }
+
+    /// The statements that create the index as `name` on `table`, and disable it again if it is a
+    /// disabled nonclustered index on the Primary.
+    pub fn create_sql(&self, table: &TableRef, name: &str) -> String {
+        let name = format!("[{}]", name.replace(']', "]]"));
+        let keys = self.key_columns.as_deref().unwrap_or_default();
+        let filter = self.filter.as_ref().map(|f| format!(" WHERE {}", f)).unwrap_or_default();
+
+        let mut create_sql = match self.index_type {
+            _ if self.is_unique_constraint => format!(
+                "ALTER TABLE {} ADD CONSTRAINT {} UNIQUE {} ({}){}",
+                table.quoted(), name, self.clustering(), keys, self.with_options()
+            ),
+            CLUSTERED_COLUMNSTORE => format!(
+                "CREATE CLUSTERED COLUMNSTORE INDEX {} ON {}{}",
+                name, table.quoted(), self.with_options()
+            ),
+            NONCLUSTERED_COLUMNSTORE => format!(
+                "CREATE NONCLUSTERED COLUMNSTORE INDEX {} ON {} ({}){}{}",
+                name, table.quoted(), self.included_columns.as_deref().unwrap_or_default(), filter, self.with_options()
+            ),
+            XML => match self.xml.as_ref().and_then(|x| x.secondary.as_ref()) {
+                Some((primary, secondary_type)) => format!(
+                    "CREATE XML INDEX {} ON {} ({}) USING XML INDEX [{}] FOR {}{}",
+                    name, table.quoted(), keys, primary.replace(']', "]]"), secondary_type, self.with_options()
+                ),
+                None => format!("CREATE PRIMARY XML INDEX {} ON {} ({}){}", name, table.quoted(), keys, self.with_options()),
+            },
+            SPATIAL => format!(
+                "CREATE SPATIAL INDEX {} ON {} ({}){}{}",
+                name, table.quoted(), keys,
+                self.spatial.as_ref().map(|s| format!(" USING {}", s.tessellation_scheme)).unwrap_or_default(),
+                self.with_options()
+            ),
+            _ => {
+                let include = self.included_columns.as_ref().map(|c| format!(" INCLUDE ({})", c)).unwrap_or_default();
+                format!(
+                    "CREATE {}{} INDEX {} ON {} ({}){}{}{}",
+                    if self.is_unique { "UNIQUE " } else { "" }, self.clustering(),
+                    name, table.quoted(), keys, include, filter, self.with_options()
+                )
+            },
+        };
+
+        // A disabled clustered index makes the whole table unreadable, so it is created enabled
+        if self.is_disabled && self.creation_order() > 0 {
+            create_sql.push_str(&format!("; ALTER INDEX {} ON {} DISABLE", name, table.quoted()));
+        }
+        create_sql
+    }
+
+    /// The statement that drops the index from `table`.
+    pub fn drop_sql(&self, table: &TableRef) -> String {
+        let name = self.name.replace(']', "]]");
+        if self.is_primary_key || self.is_unique_constraint {
+            format!("ALTER TABLE {} DROP CONSTRAINT [{}]", table.quoted(), name)
+        } else {
+            format!("DROP INDEX [{}] ON {}", name, table.quoted())
+        }
+    }
+
+    /// ` WITH (...)` holding the options the index type accepts, empty if there are none.
+    fn with_options(&self) -> String {
+        let on_off = |value: bool| if value { "ON" } else { "OFF" };
+        let mut options = Vec::new();
+
+        if let Some(spatial) = &self.spatial {
+            if let Some((xmin, ymin, xmax, ymax)) = spatial.bounding_box {
+                options.push(format!("BOUNDING_BOX = ({}, {}, {}, {})", xmin, ymin, xmax, ymax));
+            }
+            if let Some(grids) = &spatial.grids {
+                options.push(format!(
+                    "GRIDS = (LEVEL_1 = {}, LEVEL_2 = {}, LEVEL_3 = {}, LEVEL_4 = {})",
+                    grids[0], grids[1], grids[2], grids[3]
+                ));
+            }
+            if let Some(cells) = spatial.cells_per_object {
+                options.push(format!("CELLS_PER_OBJECT = {}", cells));
+            }
+        }
+
+        match self.index_type {
+            // Columnstore indexes only take their archival compression
+            CLUSTERED_COLUMNSTORE | NONCLUSTERED_COLUMNSTORE => {
+                if self.compression.as_deref() == Some("COLUMNSTORE_ARCHIVE") {
+                    options.push("DATA_COMPRESSION = COLUMNSTORE_ARCHIVE".to_string());
+                }
+            },
+            _ => {
+                options.push(format!("PAD_INDEX = {}", on_off(self.is_padded)));
+                if self.fill_factor > 0 {
+                    options.push(format!("FILLFACTOR = {}", self.fill_factor));
+                }
+                if self.index_type == CLUSTERED || self.index_type == NONCLUSTERED {
+                    options.push(format!("IGNORE_DUP_KEY = {}", on_off(self.ignore_dup_key)));
+                }
+                options.push(format!("ALLOW_ROW_LOCKS = {}", on_off(self.allow_row_locks)));
+                options.push(format!("ALLOW_PAGE_LOCKS = {}", on_off(self.allow_page_locks)));
+                if let Some(compression) = self.compression.as_deref().filter(|c| ["ROW", "PAGE"].contains(c)) {
+                    options.push(format!("DATA_COMPRESSION = {}", compression));
+                }
+            },
+        }
+
+        if options.is_empty() {
+            String::new()
+        } else {
+            format!(" WITH ({})", options.join(", "))
+        }
+    }
+}
+
+/// Which Replica indexes to drop, nonclustered ones before the clustered index, and which Primary
+/// indexes to create under which name, the clustered index first. Replica indexes missing on the
+/// Primary or declared differently there are dropped and recreated; an index the Primary declares
+/// in a way that cannot be scripted is left alone. Primary keys are created with the table.
+pub fn sync_plan<'a>(
+    p_indexes: &'a [IndexDefinition],
+    r_indexes: &'a [IndexDefinition],
+    suffix: &str,
+) -> (Vec<&'a IndexDefinition>, Vec<(&'a IndexDefinition, String)>) {
+    let p_indexes: Vec<&IndexDefinition> = p_indexes.iter().filter(|i| !i.is_primary_key).collect();
+
+    // Index names are per table; unique constraint names are per schema
+    let replica_index_name = |i: &IndexDefinition| {
+        if i.is_unique_constraint { format!("{}{}", i.name, suffix) } else { i.name.clone() }
+    };
+    let stale = |r: &IndexDefinition| match p_indexes.iter().find(|p| replica_index_name(p) == r.name) {
+        Some(p) => p.is_supported() && !p.same_definition(r),
+        None => true,
+    };
+
+    let mut dropped: Vec<&IndexDefinition> = r_indexes.iter().filter(|i| !i.is_primary_key && stale(i)).collect();
+    dropped.sort_by_key(|i| std::cmp::Reverse(i.creation_order()));
+    let kept: Vec<&String> = r_indexes.iter().filter(|i| !i.is_primary_key && !stale(i)).map(|i| &i.name).collect();
+
+    let mut created: Vec<(&IndexDefinition, String)> = p_indexes.into_iter()
+        .filter(|i| i.is_supported())
+        .map(|i| (i, replica_index_name(i)))
+        .filter(|(_, name)| !kept.contains(&name))
+        .collect();
+    created.sort_by_key(|(i, _)| i.creation_order());
+    (dropped, created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(index_type: i32, key_columns: &str) -> IndexDefinition {
+        IndexDefinition {
+            name: "IX_Test".to_string(),
+            index_type,
+            is_primary_key: false,
+            is_unique: false,
+            is_unique_constraint: false,
+            is_disabled: false,
+            key_columns: Some(key_columns.to_string()).filter(|c| !c.is_empty()),
+            included_columns: None,
+            filter: None,
+            fill_factor: 0,
+            is_padded: false,
+            ignore_dup_key: false,
+            allow_row_locks: true,
+            allow_page_locks: true,
+            compression: Some("NONE".to_string()),
+            xml: None,
+            spatial: None,
+        }
+    }
+
+    fn table() -> TableRef {
+        TableRef::new("dbo", "Orders")
+    }
+
+    const DEFAULT_OPTIONS: &str = "PAD_INDEX = OFF, IGNORE_DUP_KEY = OFF, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = ON";
+
+    #[test]
+    fn scripts_nonclustered_index_with_include_and_filter() {
+        let mut idx = index(NONCLUSTERED, "[CustomerId], [OrderDate] DESC");
+        idx.included_columns = Some("[Total], [Status]".to_string());
+        idx.filter = Some("([Status]=(1))".to_string());
+        assert_eq!(
+            idx.create_sql(&table(), "IX_Test"),
+            format!(
+                "CREATE NONCLUSTERED INDEX [IX_Test] ON [dbo].[Orders] ([CustomerId], [OrderDate] DESC) INCLUDE ([Total], [Status]) WHERE ([Status]=(1)) WITH ({})",
+                DEFAULT_OPTIONS
+            )
+        );
+    }
+
+    #[test]
+    fn compares_definitions_without_names() {
+        let idx = index(NONCLUSTERED, "[CustomerId]");
+        let mut renamed = idx.clone();
+        renamed.name = "IX_Other".to_string();
+        assert!(idx.same_definition(&renamed));
+
+        let mut included = idx.clone();
+        included.included_columns = Some("[Total]".to_string());
+        assert!(!idx.same_definition(&included));
+
+        let mut filtered = idx.clone();
+        filtered.filter = Some("([Status]=(1))".to_string());
+        assert!(!idx.same_definition(&filtered));
+
+        let mut padded = idx.clone();
+        padded.fill_factor = 80;
+        assert!(!idx.same_definition(&padded));
+
+        assert!(!idx.same_definition(&index(CLUSTERED, "[CustomerId]")));
+    }
+
+    #[test]
+    fn scripts_unique_clustered_index_with_options() {
+        let mut idx = index(CLUSTERED, "[Code]");
+        idx.is_unique = true;
+        idx.fill_factor = 80;
+        idx.is_padded = true;
+        idx.ignore_dup_key = true;
+        idx.allow_page_locks = false;
+        idx.compression = Some("PAGE".to_string());
+        assert_eq!(
+            idx.create_sql(&table(), "IX_Test"),
+            "CREATE UNIQUE CLUSTERED INDEX [IX_Test] ON [dbo].[Orders] ([Code]) WITH (PAD_INDEX = ON, FILLFACTOR = 80, IGNORE_DUP_KEY = ON, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = OFF, DATA_COMPRESSION = PAGE)"
+        );
+    }
+
+    #[test]
+    fn scripts_unique_constraint() {
+        let mut idx = index(NONCLUSTERED, "[Code]");
+        idx.is_unique = true;
+        idx.is_unique_constraint = true;
+        assert_eq!(
+            idx.create_sql(&table(), "UQ_Code__ct_reload"),
+            format!("ALTER TABLE [dbo].[Orders] ADD CONSTRAINT [UQ_Code__ct_reload] UNIQUE NONCLUSTERED ([Code]) WITH ({})", DEFAULT_OPTIONS)
+        );
+    }
+
+    #[test]
+    fn recreates_replica_indexes_that_differ() {
+        // The Replica table already exists; IX_Test gained an included column on the Primary
+        let mut primary = index(NONCLUSTERED, "[CustomerId]");
+        primary.included_columns = Some("[Total]".to_string());
+        let mut added = index(NONCLUSTERED, "[OrderDate]");
+        added.name = "IX_Added".to_string();
+        let mut gone = index(NONCLUSTERED, "[Status]");
+        gone.name = "IX_Gone".to_string();
+        let mut pk = index(CLUSTERED, "[Id]");
+        pk.name = "PK_Orders".to_string();
+        pk.is_primary_key = true;
+
+        let p_indexes = vec![pk.clone(), primary.clone(), added.clone()];
+        let r_indexes = vec![pk, index(NONCLUSTERED, "[CustomerId]"), gone.clone()];
+        let (dropped, created) = sync_plan(&p_indexes, &r_indexes, "");
+
+        let dropped: Vec<&str> = dropped.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(dropped, vec!["IX_Test", "IX_Gone"]);
+        assert_eq!(created, vec![(&primary, "IX_Test".to_string()), (&added, "IX_Added".to_string())]);
+
+        // Once recreated, nothing is left to do
+        let (dropped, created) = sync_plan(&p_indexes, &p_indexes, "");
+        assert!(dropped.is_empty() && created.is_empty());
+    }
+
+    #[test]
+    fn drops_constraints_and_indexes() {
+        let mut idx = index(NONCLUSTERED, "[Code]");
+        assert_eq!(idx.drop_sql(&table()), "DROP INDEX [IX_Test] ON [dbo].[Orders]");
+        idx.is_unique_constraint = true;
+        assert_eq!(idx.drop_sql(&table()), "ALTER TABLE [dbo].[Orders] DROP CONSTRAINT [IX_Test]");
+    }
+
+    #[test]
+    fn scripts_primary_key_clause() {
+        let mut idx = index(NONCLUSTERED, "[Id] DESC");
+        idx.is_primary_key = true;
+        assert_eq!(idx.primary_key_clause(), format!("PRIMARY KEY NONCLUSTERED ([Id] DESC) WITH ({})", DEFAULT_OPTIONS));
+    }
+
+    #[test]
+    fn scripts_columnstore_indexes() {
+        let mut cci = index(CLUSTERED_COLUMNSTORE, "");
+        cci.compression = Some("COLUMNSTORE".to_string());
+        assert_eq!(cci.create_sql(&table(), "CCI"), "CREATE CLUSTERED COLUMNSTORE INDEX [CCI] ON [dbo].[Orders]");
+
+        let mut ncci = index(NONCLUSTERED_COLUMNSTORE, "");
+        ncci.included_columns = Some("[CustomerId], [Total]".to_string());
+        ncci.compression = Some("COLUMNSTORE_ARCHIVE".to_string());
+        assert_eq!(
+            ncci.create_sql(&table(), "NCCI"),
+            "CREATE NONCLUSTERED COLUMNSTORE INDEX [NCCI] ON [dbo].[Orders] ([CustomerId], [Total]) WITH (DATA_COMPRESSION = COLUMNSTORE_ARCHIVE)"
+        );
+    }
+
+    #[test]
+    fn scripts_xml_indexes() {
+        let mut primary = index(XML, "[Document]");
+        primary.xml = Some(XmlIndex { secondary: None, is_selective: false });
+        assert_eq!(
+            primary.create_sql(&table(), "PXML"),
+            "CREATE PRIMARY XML INDEX [PXML] ON [dbo].[Orders] ([Document]) WITH (PAD_INDEX = OFF, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = ON)"
+        );
+
+        let mut secondary = index(XML, "[Document]");
+        secondary.xml = Some(XmlIndex { secondary: Some(("PXML".to_string(), "PATH".to_string())), is_selective: false });
+        assert_eq!(
+            secondary.create_sql(&table(), "SXML"),
+            "CREATE XML INDEX [SXML] ON [dbo].[Orders] ([Document]) USING XML INDEX [PXML] FOR PATH WITH (PAD_INDEX = OFF, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = ON)"
+        );
+        assert!(primary.creation_order() < secondary.creation_order());
+
+        let mut selective = index(XML, "[Document]");
+        selective.xml = Some(XmlIndex { secondary: None, is_selective: true });
+        assert!(!selective.is_supported());
+    }
+
+    #[test]
+    fn scripts_spatial_indexes() {
+        let mut geometry = index(SPATIAL, "[Shape]");
+        geometry.spatial = Some(SpatialIndex {
+            tessellation_scheme: "GEOMETRY_GRID".to_string(),
+            bounding_box: Some((0.0, 0.0, 500.5, 200.0)),
+            grids: Some(["MEDIUM".to_string(), "MEDIUM".to_string(), "HIGH".to_string(), "LOW".to_string()]),
+            cells_per_object: Some(16),
+        });
+        assert_eq!(
+            geometry.create_sql(&table(), "SIX"),
+            "CREATE SPATIAL INDEX [SIX] ON [dbo].[Orders] ([Shape]) USING GEOMETRY_GRID WITH (BOUNDING_BOX = (0, 0, 500.5, 200), GRIDS = (LEVEL_1 = MEDIUM, LEVEL_2 = MEDIUM, LEVEL_3 = HIGH, LEVEL_4 = LOW), CELLS_PER_OBJECT = 16, PAD_INDEX = OFF, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = ON)"
+        );
+
+        let mut geography = index(SPATIAL, "[Location]");
+        geography.spatial = Some(SpatialIndex {
+            tessellation_scheme: "GEOGRAPHY_AUTO_GRID".to_string(),
+            bounding_box: None,
+            grids: None,
+            cells_per_object: Some(12),
+        });
+        assert_eq!(
+            geography.create_sql(&table(), "SIX"),
+            "CREATE SPATIAL INDEX [SIX] ON [dbo].[Orders] ([Location]) USING GEOGRAPHY_AUTO_GRID WITH (CELLS_PER_OBJECT = 12, PAD_INDEX = OFF, ALLOW_ROW_LOCKS = ON, ALLOW_PAGE_LOCKS = ON)"
+        );
+    }
+
+    #[test]
+    fn scripts_disabled_index() {
+        let mut idx = index(NONCLUSTERED, "[CustomerId]");
+        idx.is_disabled = true;
+        assert!(idx.create_sql(&table(), "IX_Test").ends_with("; ALTER INDEX [IX_Test] ON [dbo].[Orders] DISABLE"));
+    }
+
+    #[test]
+    fn skips_unsupported_indexes() {
+        assert!(!index(7, "[Id]").is_supported());
+        assert!(!index(NONCLUSTERED, "").is_supported());
+        assert!(index(CLUSTERED_COLUMNSTORE, "").is_supported());
+    }
+}
@@ -0,0 +1,148 @@
+use sqlx::mssql::MssqlConnection;
+use crate::bulk::BulkStage;
+use crate::lob;
+use crate::sync::{build_insert_sql, insert_rows};
+use crate::table::TableRef;
+use crate::value::{self, ColumnSpec, SqlValue};
+
+// Loaders for the force full load. Each page is written in one Replica transaction, whichever
+// loader writes it; the bulk loader streams it through a staging table (see `bulk`).
+
+/// SQL Server accepts at most 1000 row constructors in one VALUES clause.
+const MAX_ROWS_PER_STATEMENT: usize = 1000;
+/// Parameters per statement, below SQL Server's limit of 2100.
+const MAX_PARAMS_PER_STATEMENT: usize = 2000;
+
+/// How the force full load writes a page of decoded rows to the Replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullLoadLoader {
+    /// One parameterised INSERT per row
+    RowByRow,
+    /// Multi-row `INSERT ... WITH (TABLOCK) VALUES` statements of up to `batch_size` rows.
+    /// Tables with LOB columns are still loaded row by row, since their values are staged per row.
+    Batched { batch_size: usize },
+    /// TDS bulk load (INSERT BULK) of each page into a staging table, in requests of up to
+    /// `batch_size` rows, copied into the table with `INSERT ... WITH (TABLOCK) SELECT`
+    Bulk { batch_size: usize },
+}
+
+impl FullLoadLoader {
+    /// `FULL_LOAD_LOADER` is `batched` (default), `bulk` or `row`; `FULL_LOAD_BATCH_SIZE` sets the
+    /// rows per statement of the batched loader and per bulk load request (default 1000).
+    pub fn from_env() -> Self {
+        let batch_size = std::env::var("FULL_LOAD_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(MAX_ROWS_PER_STATEMENT);
+
+        match std::env::var("FULL_LOAD_LOADER").as_deref() {
+            Ok("row") => FullLoadLoader::RowByRow,
+            Ok("batched") | Err(_) => FullLoadLoader::Batched { batch_size },
+            Ok("bulk") => FullLoadLoader::Bulk { batch_size },
+            Ok(other) => {
+                log::warn!("Unknown FULL_LOAD_LOADER '{}'; using batched", other);
+                FullLoadLoader::Batched { batch_size }
+            }
+        }
+    }
+
+    /// Opens the staging table of the bulk loader for a load of `columns`; `None` for the other
+    /// loaders.
+    pub async fn start(&self, columns: &[&ColumnSpec]) -> Result<Option<BulkStage>, Box<dyn std::error::Error + Send + Sync>> {
+        match self {
+            FullLoadLoader::Bulk { batch_size } => Ok(Some(BulkStage::create(columns, *batch_size).await?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Inserts `rows` into the (empty) replica table on the given transaction connection.
+    /// `stage` is the bulk loader's staging table opened by `start`.
+    pub async fn load(
+        &self,
+        conn: &mut MssqlConnection,
+        stage: Option<&mut BulkStage>,
+        table: &TableRef,
+        columns: &[&ColumnSpec],
+        has_identity: bool,
+        rows: Vec<Vec<SqlValue>>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let batch_size = match (self, stage) {
+            (FullLoadLoader::Bulk { .. }, Some(stage)) => {
+                stage.fill(columns, rows).await?;
+                sqlx::query(&stage.insert_sql(table, columns, has_identity)).execute(&mut *conn).await?;
+                return Ok(());
+            }
+            (FullLoadLoader::Bulk { .. }, None) => return Err("The bulk loader has no staging table".into()),
+            (FullLoadLoader::Batched { batch_size }, _) if !lob::has_lob(columns) => *batch_size,
+            _ => {
+                let insert_sql = build_insert_sql(table, columns, has_identity);
+                return Ok(insert_rows(conn, &insert_sql, columns, rows).await?);
+            }
+        };
+
+        let bound_params = columns.len().max(1);
+        let rows_per_statement = batch_size
+            .min(MAX_ROWS_PER_STATEMENT)
+            .min(MAX_PARAMS_PER_STATEMENT / bound_params)
+            .max(1);
+
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let batch: Vec<Vec<SqlValue>> = rows.by_ref().take(rows_per_statement).collect();
+            let insert_sql = build_batch_insert_sql(table, columns, has_identity, batch.len());
+            let mut query_builder = sqlx::query(&insert_sql);
+            for values in batch {
+                query_builder = value::bind_row(query_builder, columns, values);
+            }
+            query_builder.execute(&mut *conn).await?;
+        }
+        Ok(())
+    }
+}
+
+/// `INSERT INTO ... WITH (TABLOCK) (...) VALUES (...), (...)` for `row_count` rows of
+/// directly bound columns.
+fn build_batch_insert_sql(table: &TableRef, columns: &[&ColumnSpec], has_identity: bool, row_count: usize) -> String {
+    let cols = columns.iter().map(|c| c.quoted()).collect::<Vec<_>>();
+    let values = (0..row_count)
+        .map(|r| format!("({})", value::value_exprs_from(columns, r * columns.len()).join(", ")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let insert = format!("INSERT INTO {} WITH (TABLOCK) ({}) VALUES {};", table.quoted(), cols.join(", "), values);
+
+    if has_identity {
+        format!("SET IDENTITY_INSERT {} ON; {}", table.quoted(), insert)
+    } else {
+        insert
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_parameters_across_rows() {
+        let table = TableRef::new("dbo", "Orders");
+        let id = ColumnSpec::new("Id", "int", None).unwrap();
+        let hash = ColumnSpec::new("Hash", "varbinary", Some(16)).unwrap();
+
+        assert_eq!(
+            build_batch_insert_sql(&table, &[&id, &hash], false, 2),
+            "INSERT INTO [dbo].[Orders] WITH (TABLOCK) ([Id], [Hash]) VALUES \
+             (@p1, CONVERT(VARBINARY(MAX), @p2, 2)), (@p3, CONVERT(VARBINARY(MAX), @p4, 2));"
+        );
+    }
+
+    #[test]
+    fn enables_identity_insert_for_identity_tables() {
+        let table = TableRef::new("dbo", "Orders");
+        let id = ColumnSpec::new("Id", "int", None).unwrap();
+
+        assert_eq!(
+            build_batch_insert_sql(&table, &[&id], true, 1),
+            "SET IDENTITY_INSERT [dbo].[Orders] ON; INSERT INTO [dbo].[Orders] WITH (TABLOCK) ([Id]) VALUES (@p1);"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use sqlx::Row;
+use sqlx::mssql::{MssqlConnection, MssqlRow};
+use crate::sync::{decode_key, key_select_list, key_values_join};
+use crate::table::TableRef;
+use crate::value::{ColumnSpec, SqlValue};
+
+// sqlx cannot read PLP (MAX) result columns or bind strings longer than nvarchar(4000), so large
+// values are read with SUBSTRING in fixed-size pieces and reassembled on the replica in a
+// session temp table (#ct_lob) before being assigned to the target column.
+
+/// UTF-16 code units per text piece; one piece always fits an nvarchar(4000) parameter.
+pub const TEXT_PIECE_CHARS: i64 = 4000;
+/// Bytes per binary piece; hex-encoded it fits an nvarchar(4000) parameter.
+pub const BINARY_PIECE_BYTES: i64 = 2000;
+/// Pieces concatenated per staging statement.
+const PIECES_PER_STATEMENT: usize = 100;
+/// Rows per piece query.
+const KEYS_PER_QUERY: usize = 100;
+/// SQL Server returns at most 4096 columns from one SELECT.
+const MAX_SELECT_COLUMNS: usize = 4096;
+
+/// Length of a LOB column expression, in UTF-16 code units (text) or bytes (binary).
+pub fn length_expr(column: &ColumnSpec, expr: &str) -> String {
+    if column.kind == crate::value::ValueKind::Binary {
+        format!("DATALENGTH(CAST({} AS VARBINARY(MAX)))", expr)
+    } else {
+        format!("DATALENGTH(CAST({} AS NVARCHAR(MAX))) / 2", expr)
+    }
+}
+
+/// Length of one piece of the column, in the unit of `length_expr`.
+fn piece_len(column: &ColumnSpec) -> i64 {
+    if column.kind == crate::value::ValueKind::Binary { BINARY_PIECE_BYTES } else { TEXT_PIECE_CHARS }
+}
+
+/// Expression returning the piece of `expr` that starts at the 1-based offset `start`.
+fn piece_expr(column: &ColumnSpec, expr: &str, start: i64) -> String {
+    if column.kind == crate::value::ValueKind::Binary {
+        format!(
+            "CONVERT(VARCHAR(4000), CAST(SUBSTRING(CAST({} AS VARBINARY(MAX)), {}, {}) AS VARBINARY({})), 2)",
+            expr, start, BINARY_PIECE_BYTES, BINARY_PIECE_BYTES
+        )
+    } else {
+        format!(
+            "CAST(SUBSTRING(CAST({} AS NVARCHAR(MAX)), {}, {}) AS NVARCHAR({}))",
+            expr, start, TEXT_PIECE_CHARS, TEXT_PIECE_CHARS
+        )
+    }
+}
+
+/// Expression that reads the value staged for the column at `index` (NULL if none was staged).
+pub fn staged_value_expr(column: &ColumnSpec, index: usize) -> String {
+    let staged_col = if column.kind == crate::value::ValueKind::Binary { "bin" } else { "txt" };
+    format!("(SELECT {} FROM #ct_lob WHERE c = {})", staged_col, index)
+}
+
+/// Fills in the content of every `SqlValue::Lob` in `rows` by reading it from the Primary in
+/// pieces. `keys[i]` is the key tuple of `rows[i]`, ordered like `key_columns`. A value whose reassembled length differs from the length
+/// reported with the row is reported as an error rather than written truncated.
+pub async fn fetch_values(
+    conn: &mut MssqlConnection,
+    table: &TableRef,
+    key_columns: &[&ColumnSpec],
+    columns: &[&ColumnSpec],
+    keys: &[Vec<SqlValue>],
+    rows: &mut [Vec<SqlValue>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let key_select = key_select_list(key_columns);
+
+    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.lob) {
+        let piece_len = piece_len(column);
+        let col_expr = format!("t.{}", column.quoted());
+        let mut start = 1;
+
+        loop {
+            // Rows whose value still has data at this offset
+            let pending: Vec<usize> = rows.iter().enumerate()
+                .filter(|(_, row)| matches!(&row[ci], SqlValue::Lob(lob) if lob.length >= start))
+                .map(|(i, _)| i)
+                .collect();
+            if pending.is_empty() {
+                break;
+            }
+            if key_columns.is_empty() {
+                return Err(format!("Column [{}] of {} needs a key to be read in pieces", column.name, table).into());
+            }
+
+            for batch in pending.chunks(KEYS_PER_QUERY) {
+                let batch_keys: Vec<Vec<SqlValue>> = batch.iter().map(|&i| keys[i].clone()).collect();
+                let piece_query = format!(
+                    "SELECT {}, {} AS [__ct_piece] FROM {} AS t {}",
+                    key_select, piece_expr(column, &col_expr, start), table.quoted(), key_values_join(key_columns, &batch_keys)?
+                );
+                let piece_rows = sqlx::query(&piece_query).fetch_all(&mut *conn).await?;
+
+                for piece_row in piece_rows {
+                    let key = decode_key(&piece_row, key_columns)?;
+                    let piece: Option<String> = piece_row.try_get("__ct_piece")?;
+                    if let (Some(&i), Some(piece)) = (batch.iter().find(|&&i| keys[i] == key), piece) {
+                        if let SqlValue::Lob(lob) = &mut rows[i][ci] {
+                            lob.pieces.push(piece);
+                        }
+                    }
+                }
+            }
+
+            start += piece_len;
+        }
+    }
+
+    check_lengths(table, columns, rows)
+}
+
+/// Number of pieces the longest value of each column of `table` is read in (0 for columns that
+/// are not LOBs), for reading whole values with `piece_select_list`.
+pub async fn max_pieces(
+    conn: &mut MssqlConnection,
+    table: &TableRef,
+    columns: &[&ColumnSpec],
+) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let mut pieces = vec![0; columns.len()];
+    let lengths = columns.iter().enumerate().filter(|(_, c)| c.lob)
+        .map(|(ci, c)| format!("CAST(MAX({}) AS BIGINT) AS [l{}]", length_expr(c, &format!("t.{}", c.quoted())), ci))
+        .collect::<Vec<_>>();
+    if lengths.is_empty() {
+        return Ok(pieces);
+    }
+
+    let query = format!("SELECT {} FROM {} AS t", lengths.join(", "), table.quoted());
+    let row = sqlx::query(&query).fetch_one(&mut *conn).await?;
+    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.lob) {
+        let max_length: Option<i64> = row.try_get(format!("l{}", ci).as_str())?;
+        pieces[ci] = pieces_for(column, max_length.unwrap_or(0));
+    }
+    Ok(pieces)
+}
+
+/// Number of pieces a value of `length` is read in.
+fn pieces_for(column: &ColumnSpec, length: i64) -> usize {
+    let piece_len = piece_len(column);
+    ((length + piece_len - 1) / piece_len) as usize
+}
+
+/// SELECT expressions returning the first `pieces[ci]` pieces of every LOB column of the table
+/// aliased `t` as `[__ct_piece_<ci>_<n>]`, so whole values are read together with their row.
+pub fn piece_select_list(columns: &[&ColumnSpec], pieces: &[usize]) -> Result<Vec<String>, String> {
+    let exprs: Vec<String> = columns.iter().enumerate()
+        .flat_map(|(ci, column)| (0..pieces[ci]).map(move |n| format!(
+            "{} AS [__ct_piece_{}_{}]",
+            piece_expr(column, &format!("t.{}", column.quoted()), 1 + n as i64 * piece_len(column)), ci, n
+        )))
+        .collect();
+    if exprs.len() + columns.len() >= MAX_SELECT_COLUMNS {
+        return Err(format!("LOB values need {} piece columns, more than one SELECT can return", exprs.len()));
+    }
+    Ok(exprs)
+}
+
+/// Moves the pieces a row was selected with by `piece_select_list` into its LOB values.
+pub fn read_pieces(row: &MssqlRow, columns: &[&ColumnSpec], pieces: &[usize], values: &mut [SqlValue]) -> Result<(), sqlx::Error> {
+    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.lob) {
+        if let SqlValue::Lob(lob) = &mut values[ci] {
+            for n in 0..pieces[ci] {
+                if lob.length <= n as i64 * piece_len(column) {
+                    break;
+                }
+                if let Some(piece) = row.try_get::<Option<String>, _>(format!("__ct_piece_{}_{}", ci, n).as_str())? {
+                    lob.pieces.push(piece);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Errors if a LOB value's reassembled length differs from the length reported with its row,
+/// rather than let it be written truncated.
+pub fn check_lengths(table: &TableRef, columns: &[&ColumnSpec], rows: &[Vec<SqlValue>]) -> Result<(), Box<dyn std::error::Error>> {
+    for (ci, column) in columns.iter().enumerate().filter(|(_, c)| c.lob) {
+        for row in rows {
+            if let SqlValue::Lob(lob) = &row[ci] {
+                let fetched = lob.fetched_length();
+                if fetched != lob.length {
+                    return Err(format!(
+                        "Column [{}] of {} was truncated or changed while being read: expected length {}, fetched {}",
+                        column.name, table, lob.length, fetched
+                    ).into());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stages the LOB values of one row in #ct_lob on the replica connection, replacing whatever
+/// was staged for the previous row. Must run on the connection that executes the row's
+/// INSERT/UPDATE built with `value::value_exprs`.
+pub async fn stage_values(
+    conn: &mut MssqlConnection,
+    columns: &[&ColumnSpec],
+    values: &[SqlValue],
+) -> Result<(), sqlx::Error> {
+    // Runs as a plain batch (no parameters), so the temp table lives for the whole session
+    sqlx::query(
+        "IF OBJECT_ID('tempdb..#ct_lob') IS NULL
+            CREATE TABLE #ct_lob (c INT PRIMARY KEY, txt NVARCHAR(MAX) NULL, bin VARBINARY(MAX) NULL);
+         DELETE FROM #ct_lob;"
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    for (ci, value) in values.iter().enumerate().filter(|(ci, _)| columns[*ci].lob) {
+        let lob = match value {
+            SqlValue::Lob(lob) => lob,
+            _ => continue,
+        };
+
+        let (staged_col, empty) = if lob.binary { ("bin", "0x") } else { ("txt", "N''") };
+        let init_sql = format!("INSERT INTO #ct_lob (c, {}) VALUES ({}, {})", staged_col, ci, empty);
+        sqlx::query(&init_sql).execute(&mut *conn).await?;
+
+        for group in lob.pieces.chunks(PIECES_PER_STATEMENT) {
+            let parts = (1..=group.len())
+                .map(|i| if lob.binary { format!("CONVERT(VARBINARY(MAX), @p{}, 2)", i) } else { format!("@p{}", i) })
+                .collect::<Vec<_>>()
+                .join(" + ");
+            let append_sql = format!("UPDATE #ct_lob SET {} = {} + {} WHERE c = {}", staged_col, staged_col, parts, ci);
+            let mut query_builder = sqlx::query(&append_sql);
+            for piece in group {
+                query_builder = query_builder.bind(piece.as_str());
+            }
+            query_builder.execute(&mut *conn).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// True if any of `columns` is moved in pieces.
+pub fn has_lob(columns: &[&ColumnSpec]) -> bool {
+    columns.iter().any(|c| c.lob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_whole_values_in_piece_columns() {
+        let id = ColumnSpec::new("Id", "int", None).unwrap();
+        let body = ColumnSpec::new("Body", "nvarchar", Some(-1)).unwrap();
+        let doc = ColumnSpec::new("Doc", "varbinary", Some(-1)).unwrap();
+        assert_eq!((pieces_for(&body, 0), pieces_for(&body, 4000), pieces_for(&body, 9000)), (0, 1, 3));
+        assert_eq!(pieces_for(&doc, 2001), 2);
+
+        let exprs = piece_select_list(&[&id, &body, &doc], &[0, 3, 1]).unwrap();
+        assert_eq!(exprs, vec![
+            "CAST(SUBSTRING(CAST(t.[Body] AS NVARCHAR(MAX)), 1, 4000) AS NVARCHAR(4000)) AS [__ct_piece_1_0]",
+            "CAST(SUBSTRING(CAST(t.[Body] AS NVARCHAR(MAX)), 4001, 4000) AS NVARCHAR(4000)) AS [__ct_piece_1_1]",
+            "CAST(SUBSTRING(CAST(t.[Body] AS NVARCHAR(MAX)), 8001, 4000) AS NVARCHAR(4000)) AS [__ct_piece_1_2]",
+            "CONVERT(VARCHAR(4000), CAST(SUBSTRING(CAST(t.[Doc] AS VARBINARY(MAX)), 1, 2000) AS VARBINARY(2000)), 2) AS [__ct_piece_2_0]",
+        ]);
+        assert!(piece_select_list(&[&body], &[5000]).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use std::env;
 use std::time::Duration;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use tokio::sync::{Semaphore, Mutex as TokioMutex};
 use sqlx::mssql::MssqlPoolOptions;
 use redis::Client;
@@ -13,6 +13,20 @@ mod state;
 mod schema;
 mod sync;
 mod ddl_events;
+mod table;
+mod columns;
+mod indexes;
+mod value;
+mod lob;
+mod loader;
+mod bulk;
+mod snapshot;
+mod checkpoint;
+mod foreign_keys;
+mod triggers;
+mod cutover;
+
+use table::TableRef;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -43,6 +57,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .connect(&replica_url)
         .await?;
 
+    // Applied versions are committed on the Replica together with the changes they cover
+    checkpoint::ensure_state_table(&replica_pool).await?;
+
     info!("Connecting to Redis...");
     let redis_client = Client::open(redis_url)?;
 
@@ -74,6 +91,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .parse::<usize>()
         .unwrap_or(1);
     
+    let sync_options = Arc::new(sync::SyncOptions::from_env());
+
+    // `backend cutover` drains and validates the Replica for promotion instead of replicating
+    if env::args().nth(1).as_deref() == Some("cutover") {
+        let report = cutover::run(&primary_pool, &replica_pool, &redis_client, &sync_options).await?;
+        if !report.safe_to_promote {
+            return Err("Cutover failed: the Replica is not safe to promote".into());
+        }
+        return Ok(());
+    }
+
     let cancel_token = CancellationToken::new();
 
     // Spawn a graceful shutdown listener
@@ -102,7 +130,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Global Concurrency State
     let semaphore = Arc::new(Semaphore::new(thread_count));
-    let active_tasks: Arc<TokioMutex<HashSet<String>>> = Arc::new(TokioMutex::new(HashSet::new()));
+    let active_tasks: Arc<TokioMutex<HashSet<TableRef>>> = Arc::new(TokioMutex::new(HashSet::new()));
 
     loop {
         if cancel_token.is_cancelled() {
@@ -110,29 +138,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             break;
         }
 
-        // Fetch all tracked tables
-        let tables_query = "
-            SELECT t.name AS TableName
-            FROM sys.change_tracking_tables ctt
-            JOIN sys.tables t ON ctt.object_id = t.object_id
-        ";
-        
-        let tables_res = sqlx::query(tables_query).fetch_all(&primary_pool).await;
+        let tables_res = schema::list_source_tables(&primary_pool).await;
         
         match tables_res {
             Ok(tables) => {
-                for row in tables {
-                    let table_name: String = sqlx::Row::get(&row, "TableName");
+                // Dispatch referenced tables before the tables that reference them
+                let tables = match foreign_keys::get_dependencies(&primary_pool).await {
+                    Ok(dependencies) => foreign_keys::dependency_order(tables, &dependencies),
+                    Err(e) => {
+                        error!("Failed to read foreign key dependencies: {}", e);
+                        tables
+                    }
+                };
+
+                // Members of a consistency group are synced together by one task
+                let mut groups: HashMap<String, Vec<TableRef>> = HashMap::new();
+
+                for table in tables {
+                    match state::get_consistency_group(&redis_client, &table.to_string()).await {
+                        Ok(Some(group)) => {
+                            groups.entry(group).or_default().push(table);
+                            continue;
+                        },
+                        Ok(None) => {},
+                        Err(e) => error!("Failed to read consistency group of {}: {}", table, e),
+                    }
                     
                     // Check if table is currently syncing, skip if it is
                     let mut tasks_guard = active_tasks.lock().await;
-                    if tasks_guard.contains(&table_name) {
-                        debug!("Table {} is already syncing, skipping iteration.", table_name);
+                    if tasks_guard.contains(&table) {
+                        debug!("Table {} is already syncing, skipping iteration.", table);
                         continue;
                     }
                     
                     // Not syncing: mark as active and spawn detached task
-                    tasks_guard.insert(table_name.clone());
+                    tasks_guard.insert(table.clone());
                     drop(tasks_guard);
 
                     let p_pool = primary_pool.clone();
@@ -141,6 +181,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let sem_clone = Arc::clone(&semaphore);
                     let active_clone = Arc::clone(&active_tasks);
                     let table_token = cancel_token.clone();
+                    let table_options = Arc::clone(&sync_options);
 
                     tokio::spawn(async move {
                         // Attempt to acquire a permit. This will hang here if SYNC_THREADS is exhausted
@@ -148,24 +189,69 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let _permit = match sem_clone.acquire().await {
                             Ok(p) => p,
                             Err(_) => {
-                                active_clone.lock().await.remove(&table_name);
+                                active_clone.lock().await.remove(&table);
                                 return;
                             }
                         };
                         
                         // Pass off to sync process
-                        if let Err(e) = sync::run_single_table_sync(&p_pool, &r_pool, &r_client, &table_name, table_token).await {
-                            error!("Sync error on table {}: {}", table_name, e);
+                        if let Err(e) = sync::run_single_table_sync(&p_pool, &r_pool, &r_client, &table, &table_options, table_token).await {
+                            error!("Sync error on table {}: {}", table, e);
                         }
 
                         // Detach from active list
-                        active_clone.lock().await.remove(&table_name);
+                        active_clone.lock().await.remove(&table);
+                    });
+                }
+
+                for (group, members) in groups {
+                    // A group waits until none of its members is still syncing
+                    let mut tasks_guard = active_tasks.lock().await;
+                    if members.iter().any(|t| tasks_guard.contains(t)) {
+                        debug!("Consistency group {} is already syncing, skipping iteration.", group);
+                        continue;
+                    }
+                    tasks_guard.extend(members.iter().cloned());
+                    drop(tasks_guard);
+
+                    let p_pool = primary_pool.clone();
+                    let r_pool = replica_pool.clone();
+                    let r_client = redis_client.clone();
+                    let sem_clone = Arc::clone(&semaphore);
+                    let active_clone = Arc::clone(&active_tasks);
+                    let group_token = cancel_token.clone();
+                    let group_options = Arc::clone(&sync_options);
+
+                    tokio::spawn(async move {
+                        let _permit = match sem_clone.acquire().await {
+                            Ok(p) => p,
+                            Err(_) => {
+                                let mut tasks_guard = active_clone.lock().await;
+                                members.iter().for_each(|t| { tasks_guard.remove(t); });
+                                return;
+                            }
+                        };
+
+                        if let Err(e) = sync::run_group_sync(&p_pool, &r_pool, &r_client, &group, &members, &group_options, group_token).await {
+                            error!("Sync error in consistency group {}: {}", group, e);
+                        }
+
+                        let mut tasks_guard = active_clone.lock().await;
+                        members.iter().for_each(|t| { tasks_guard.remove(t); });
                     });
                 }
             },
             Err(e) => error!("Failed to fetch table list: {}", e),
         }
 
+        // Turn foreign keys disabled for the apply back on once neither of their tables is syncing
+        if sync_options.foreign_key_mode == foreign_keys::ForeignKeyMode::NoCheck {
+            let active = active_tasks.lock().await.clone();
+            if let Err(e) = foreign_keys::revalidate(&replica_pool, &redis_client, &active).await {
+                error!("Foreign key re-validation error: {}", e);
+            }
+        }
+
         // We run Views & Routines sequentially in the main loop every 5s as they are cheap DDL
         if let Err(e) = schema::sync_views(&primary_pool, &replica_pool).await {
             error!("View sync error: {}", e);
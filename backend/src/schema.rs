@@ -1,242 +1,680 @@
+use std::collections::HashMap;
 use sqlx::{Pool, Mssql, Row};
-use log::info;
+use sqlx::mssql::MssqlConnection;
+use log::{info, warn};
+use crate::columns::{ColumnDefinition, DEFINITION_SELECT};
+use crate::indexes::{self, IndexDefinition};
+use crate::table::{TableRef, SHADOW_SUFFIX};
+
+/// Every change-tracked table on the Primary, plus tables without a primary key (which cannot be
+/// change-tracked and are replicated by snapshot diff once enabled).
+pub async fn list_source_tables(primary_pool: &Pool<Mssql>) -> Result<Vec<TableRef>, sqlx::Error> {
+    list_tables(primary_pool, "
+        AND (t.object_id IN (SELECT object_id FROM sys.change_tracking_tables)
+             OR OBJECTPROPERTY(t.object_id, 'TableHasPrimaryKey') = 0)
+    ").await
+}
+
+/// Every user table on the Primary, including tables with a primary key but without change
+/// tracking, which `list_source_tables` leaves out.
+pub async fn list_user_tables(primary_pool: &Pool<Mssql>) -> Result<Vec<TableRef>, sqlx::Error> {
+    list_tables(primary_pool, "").await
+}
 
+async fn list_tables(primary_pool: &Pool<Mssql>, filter: &str) -> Result<Vec<TableRef>, sqlx::Error> {
+    let tables_query = format!(
+        "SELECT s.name AS SchemaName, t.name AS TableName
+         FROM sys.tables t
+         JOIN sys.schemas s ON t.schema_id = s.schema_id
+         WHERE t.is_ms_shipped = 0 {}",
+        filter
+    );
+    let rows = sqlx::query(&tables_query).fetch_all(primary_pool).await?;
+    Ok(rows.iter()
+        .map(|row| TableRef::new(&row.get::<String, _>("SchemaName"), &row.get::<String, _>("TableName")))
+        .collect())
+}
+
+/// Outcome of `ensure_table_exists`.
+pub struct EnsuredTable {
+    /// Columns of the table on the Primary
+    pub columns: Vec<ColumnDefinition>,
+    /// Replica columns that no longer exist on the Primary
+    pub retired: Vec<ColumnDefinition>,
+}
+
+/// Creates `table` on the Replica, or renames, adds and alters its columns to match the Primary.
+/// `previous_names` maps the Primary's column ids to their names as last seen, so a column whose
+/// id kept its name changed is renamed instead of added. Alterations that could lose data are
+/// only applied with `allow_narrowing`.
 pub async fn ensure_table_exists(
     primary_pool: &Pool<Mssql>,
     replica_pool: &Pool<Mssql>,
-    table_name: &str
-) -> Result<(), Box<dyn std::error::Error>> {
+    table: &TableRef,
+    previous_names: &HashMap<i32, String>,
+    allow_narrowing: bool,
+) -> Result<EnsuredTable, Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+
     // Check if table exists in Replica
     let check_query = format!(
-        "SELECT COUNT(*) FROM INFORMATION_SCHEMA.TABLES WHERE TABLE_NAME = '{}'", 
-        table_name
+        "SELECT COUNT(*) FROM INFORMATION_SCHEMA.TABLES WHERE TABLE_SCHEMA = '{}' AND TABLE_NAME = '{}'", 
+        table.schema_literal(), table.name_literal()
     );
     let exists: i32 = sqlx::query_scalar(&check_query)
         .fetch_one(replica_pool)
         .await?;
 
     // Get column definitions from Primary first
-    let columns_query = format!(
-        "SELECT 
-            c.COLUMN_NAME, 
-            c.DATA_TYPE, 
-            c.CHARACTER_MAXIMUM_LENGTH, 
-            c.IS_NULLABLE,
-            c.COLUMN_DEFAULT,
-            c.NUMERIC_PRECISION,
-            c.NUMERIC_SCALE,
-            c.DATETIME_PRECISION,
-            COLUMNPROPERTY(OBJECT_ID(c.TABLE_SCHEMA + '.' + c.TABLE_NAME), c.COLUMN_NAME, 'IsIdentity') as IsIdentity
-         FROM INFORMATION_SCHEMA.COLUMNS c
-         WHERE c.TABLE_NAME = '{}' 
-         ORDER BY c.ORDINAL_POSITION",
-        table_name
-    );
+    let columns = get_column_definitions(primary_pool, table).await?;
 
-    let rows = sqlx::query(&columns_query)
-        .fetch_all(primary_pool)
-        .await?;
-
-    if rows.is_empty() {
+    if columns.is_empty() {
         return Err(format!("Table {} not found on Primary", table_name).into());
     }
 
     if exists == 0 {
         info!("Table {} does not exist in Replica. Creating...", table_name);
+        create_table(primary_pool, replica_pool, table, table, &columns).await?;
 
-        let mut create_sql = format!("CREATE TABLE [{}] (", table_name);
-        let mut pk_columns = Vec::new();
+    } else {
+       let mut replica_columns = get_column_definitions(replica_pool, table).await?;
+
+       // Columns renamed on the Primary since the last pass, unless the rename already reached
+       // the Replica (e.g. through the DDL event consumer)
+       for column in &columns {
+           let Some(old_name) = previous_names.get(&column.column_id) else { continue };
+           if *old_name == column.name
+               || columns.iter().any(|c| c.name == *old_name)
+               || replica_columns.iter().any(|c| c.name == column.name)
+           {
+               continue;
+           }
+           if let Some(existing) = replica_columns.iter_mut().find(|c| c.name == *old_name) {
+               info!("Column {} of {} was renamed to {} on the Primary. Renaming...", old_name, table_name, column.name);
+               rename_column(replica_pool, table, old_name, &column.name).await?;
+               existing.name = column.name.clone();
+           }
+       }
 
-        for (i, row) in rows.iter().enumerate() {
-            let col_name: String = row.get("COLUMN_NAME");
-            let data_type: String = row.get("DATA_TYPE");
-            let max_len: Option<i32> = row.try_get("CHARACTER_MAXIMUM_LENGTH").ok();
-            let is_nullable: String = row.get("IS_NULLABLE");
-            let col_default: Option<String> = row.try_get("COLUMN_DEFAULT").ok();
-            let is_identity: Option<i32> = row.try_get("IsIdentity").ok();
-            let dt_prec: Option<i16> = row.try_get("DATETIME_PRECISION").ok();
+       // Check for missing columns
+
+       for column in &columns {
+           match replica_columns.iter().find(|c| c.name == column.name) {
+               None => {
+                   info!("Column {} missing in Replica table {}. Adding...", column.name, table_name);
+
+                   let add_sql = format!("ALTER TABLE {} ADD {}", table.quoted(), column.render());
+                   info!("Executing: {}", add_sql);
+                   sqlx::query(&add_sql).execute(replica_pool).await?;
+               },
+               // A failed alteration leaves the column as it is and does not hold up the sync
+               Some(existing) => {
+                   if let Err(e) = alter_column(replica_pool, table, existing, column, allow_narrowing).await {
+                       warn!("Failed to alter column {} of Replica table {}: {}", column.name, table_name, e);
+                   }
+               },
+           }
+       }
 
-            if i > 0 {
-                create_sql.push_str(", ");
-            }
+       // Indexes changed on the Primary since the table was created
+       sync_indexes(primary_pool, replica_pool, table).await?;
 
-            create_sql.push_str(&format!("[{}] {}", col_name, data_type));
+       let retired = replica_columns.into_iter()
+           .filter(|existing| !columns.iter().any(|c| c.name == existing.name))
+           .collect();
+       return Ok(EnsuredTable { columns, retired });
+    }
 
-            if let Some(len) = max_len {
-                if len == -1 {
-                    create_sql.push_str("(MAX)");
-                } else if data_type == "nvarchar" || data_type == "varchar" || data_type == "varbinary" {
-                    create_sql.push_str(&format!("({})", len));
-                }
-            } else if ["datetime2", "datetimeoffset", "time"].contains(&data_type.as_str()) {
-                if let Some(prec) = dt_prec {
-                    create_sql.push_str(&format!("({})", prec));
-                }
-            }
+    Ok(EnsuredTable { columns, retired: Vec::new() })
+}
 
-            if let Some(1) = is_identity {
-                create_sql.push_str(" IDENTITY(1,1)");
-            }
+/// Brings the `existing` Replica column in line with the Primary's definition: its type,
+/// collation and nullability with `ALTER COLUMN`, and its default constraint. Indexes on the
+/// column are dropped and recreated around the `ALTER COLUMN`. Alterations that
+/// are not widening are refused unless `allow_narrowing` is set.
+async fn alter_column(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    existing: &ColumnDefinition,
+    column: &ColumnDefinition,
+    allow_narrowing: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut alter = column.needs_alter(existing);
+    let default_changed = column.default != existing.default;
 
-            if is_nullable == "NO" {
-                create_sql.push_str(" NOT NULL");
-            } else {
-                create_sql.push_str(" NULL");
-            }
+    if alter && !allow_narrowing && !column.is_widening_of(existing) {
+        warn!(
+            "Refusing narrowing change of column {} on {}: {} -> {} (set mssql_sync:allow_narrowing_alters:{} to \"true\" to apply it)",
+            column.name, table, existing.render_alter(), column.render_alter(), table
+        );
+        alter = false;
+    }
+    if !alter && !default_changed {
+        return Ok(());
+    }
 
-            if let Some(def_val) = col_default {
-                create_sql.push_str(&format!(" DEFAULT {}", def_val));
-            }
-        }
+    let mut tx = replica_pool.begin().await?;
 
-        // Get PK
-        let pk_query = format!(
-            "SELECT COLUMN_NAME 
-             FROM INFORMATION_SCHEMA.KEY_COLUMN_USAGE 
-             WHERE OBJECTPROPERTY(OBJECT_ID(CONSTRAINT_SCHEMA + '.' + CONSTRAINT_NAME), 'IsPrimaryKey') = 1 
-             AND TABLE_NAME = '{}'",
-            table_name
-        );
+    // The default constraint is recreated around the alteration, which it would otherwise block
+    if existing.default.is_some() {
+        sqlx::query(&drop_default_sql(table, existing)).execute(&mut *tx).await?;
+    }
 
-        let pk_rows = sqlx::query(&pk_query)
-            .fetch_all(primary_pool)
-            .await?;
-        
-        for row in pk_rows {
-            pk_columns.push(format!("[{}]", row.get::<String, _>("COLUMN_NAME")));
+    // So are the indexes on the column, which are recreated from their Replica definitions. One
+    // that cannot be scripted is left in place and the alteration fails.
+    let mut dependent = Vec::new();
+    if alter {
+        dependent = get_dependent_indexes(&mut tx, table, existing.column_id).await?;
+        dependent.retain(|i| i.is_supported());
+        dependent.sort_by_key(|i| std::cmp::Reverse(i.creation_order()));
+        for index in &dependent {
+            info!("Dropping index/constraint {} on table {} to alter column {}", index.name, table, column.name);
+            sqlx::query(&index.drop_sql(table)).execute(&mut *tx).await?;
         }
 
-        if !pk_columns.is_empty() {
-            create_sql.push_str(&format!(", PRIMARY KEY ({})", pk_columns.join(", ")));
-        }
+        let alter_sql = format!("ALTER TABLE {} ALTER COLUMN {}", table.quoted(), column.render_alter());
+        info!("Executing: {}", alter_sql);
+        sqlx::query(&alter_sql).execute(&mut *tx).await?;
+    }
 
-        create_sql.push(')');
+    for index in dependent.iter().rev() {
+        info!("Recreating index/constraint {} on table {}", index.name, table);
+        sqlx::query(&index.create_sql(table, &index.name)).execute(&mut *tx).await?;
+    }
 
-        info!("Executing: {}", create_sql);
-        sqlx::query(&create_sql).execute(replica_pool).await?;
-        
-        let enable_ct_query = format!(
-            "ALTER TABLE [{}] ENABLE CHANGE_TRACKING WITH (TRACK_COLUMNS_UPDATED = ON)",
-            table_name
+    if let Some(default) = &column.default {
+        let default_sql = format!(
+            "ALTER TABLE {} ADD DEFAULT {} FOR [{}]",
+            table.quoted(), default, column.name.replace(']', "]]")
         );
-        let _ = sqlx::query(&enable_ct_query).execute(replica_pool).await;
+        info!("Executing: {}", default_sql);
+        sqlx::query(&default_sql).execute(&mut *tx).await?;
+    }
 
-        // Initialize schema objects (Indexes, Unique constraints, Foreign keys) for the newly created table
-        info!("Initializing indexes and constraints for new table {}", table_name);
-        sync_schema_objects(primary_pool, replica_pool, table_name).await?;
+    tx.commit().await?;
+    Ok(())
+}
 
-    } else {
-       // Table exists, check for missing columns and property mismatches
-       let replica_cols_query = format!(
-           "SELECT 
-               COLUMN_NAME, 
-               DATA_TYPE, 
-               CHARACTER_MAXIMUM_LENGTH, 
-               IS_NULLABLE, 
-               COLUMN_DEFAULT,
-               NUMERIC_PRECISION,
-               NUMERIC_SCALE,
-               DATETIME_PRECISION
-            FROM INFORMATION_SCHEMA.COLUMNS WHERE TABLE_NAME = '{}'",
-           table_name
-       );
-       let replica_rows = sqlx::query(&replica_cols_query).fetch_all(replica_pool).await?;
-       let replica_col_names: Vec<String> = replica_rows.iter().map(|r| r.get("COLUMN_NAME")).collect();
-
-       for row in &rows {
-           let col_name: String = row.get("COLUMN_NAME");
-           
-           if !replica_col_names.contains(&col_name) {
-               // Column missing logic (same as before)
-               info!("Column {} missing in Replica table {}. Adding...", col_name, table_name);
-               
-               let data_type: String = row.get("DATA_TYPE");
-               let max_len: Option<i32> = row.try_get("CHARACTER_MAXIMUM_LENGTH").ok();
-               let is_nullable: String = row.get("IS_NULLABLE");
-               let col_default: Option<String> = row.try_get("COLUMN_DEFAULT").ok();
-               let is_identity: Option<i32> = row.try_get("IsIdentity").ok();
-               let numeric_precision: Option<u8> = row.try_get("NUMERIC_PRECISION").ok();
-               let numeric_scale: Option<i32> = row.try_get("NUMERIC_SCALE").ok();
-               let dt_prec: Option<i16> = row.try_get("DATETIME_PRECISION").ok();
-               
-               let mut add_sql = format!("ALTER TABLE [{}] ADD [{}] {}", table_name, col_name, data_type);
-               
-               if data_type == "decimal" || data_type == "numeric" {
-                   if let (Some(p), Some(s)) = (numeric_precision, numeric_scale) {
-                       add_sql.push_str(&format!("({}, {})", p, s));
-                   }
-               } else if let Some(len) = max_len {
-                   if len == -1 {
-                       add_sql.push_str("(MAX)");
-                   } else if ["nvarchar", "varchar", "varbinary", "char", "nchar"].contains(&data_type.as_str()) {
-                       add_sql.push_str(&format!("({})", len));
-                   }
-               } else if ["datetime2", "datetimeoffset", "time"].contains(&data_type.as_str()) {
-                   if let Some(prec) = dt_prec {
-                       add_sql.push_str(&format!("({})", prec));
-                   }
-               }
-
-               if let Some(1) = is_identity {
-                   add_sql.push_str(" IDENTITY(1,1)");
-               }
-               
-               if is_nullable == "NO" {
-                   add_sql.push_str(" NOT NULL");
-               } else {
-                   add_sql.push_str(" NULL");
-               }
-               
-               if let Some(def_val) = &col_default {
-                   add_sql.push_str(&format!(" DEFAULT {}", def_val));
-               }
-               
-               info!("Executing: {}", add_sql);
-               sqlx::query(&add_sql).execute(replica_pool).await?;
-           }
-       }
-    }
+/// Drops the default constraint of `column` on the Replica, if it has one.
+fn drop_default_sql(table: &TableRef, column: &ColumnDefinition) -> String {
+    format!(
+        "DECLARE @df NVARCHAR(300) = (SELECT QUOTENAME(name) FROM sys.default_constraints WHERE parent_object_id = {} AND parent_column_id = {});
+         IF @df IS NOT NULL EXEC(N'ALTER TABLE {} DROP CONSTRAINT ' + @df);",
+        table.object_id(), column.column_id, table.quoted().replace('\'', "''")
+    )
+}
 
+/// Renames a column of `table` on the Replica.
+pub async fn rename_column(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    column: &str,
+    new_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rename_sql = format!(
+        "EXEC sp_rename N'{}', N'{}', N'COLUMN'",
+        format!("{}.[{}]", table.quoted(), column.replace(']', "]]")).replace('\'', "''"),
+        new_name.replace('\'', "''")
+    );
+    info!("Executing: {}", rename_sql);
+    sqlx::query(&rename_sql).execute(replica_pool).await?;
     Ok(())
 }
 
-pub async fn sync_schema_objects(
+/// Drops a column of `table` on the Replica, together with its default constraint.
+pub async fn drop_column(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    column: &ColumnDefinition,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tx = replica_pool.begin().await?;
+    sqlx::query(&drop_default_sql(table, column)).execute(&mut *tx).await?;
+
+    let drop_sql = format!("ALTER TABLE {} DROP COLUMN [{}]", table.quoted(), column.name.replace(']', "]]"));
+    info!("Executing: {}", drop_sql);
+    sqlx::query(&drop_sql).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Column definitions of `table` in column order, as used to create it (empty if it does not
+/// exist).
+async fn get_column_definitions(
+    pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<Vec<ColumnDefinition>, Box<dyn std::error::Error>> {
+    let columns_query = format!(
+        "SELECT {}
+         FROM sys.columns c
+         JOIN sys.types t ON t.user_type_id = c.user_type_id
+         LEFT JOIN sys.types bt ON t.is_user_defined = 1 AND bt.user_type_id = c.system_type_id
+         LEFT JOIN sys.identity_columns ic ON ic.object_id = c.object_id AND ic.column_id = c.column_id
+         LEFT JOIN sys.default_constraints dc ON dc.object_id = c.default_object_id
+         WHERE c.object_id = {}
+         ORDER BY c.column_id",
+        DEFINITION_SELECT, table.object_id()
+    );
+
+    let rows = sqlx::query(&columns_query).fetch_all(pool).await?;
+    Ok(rows.iter().map(ColumnDefinition::from_row).collect::<Result<_, _>>()?)
+}
+
+/// Indexes, unique constraints and the primary key of `table`, as used to script them.
+async fn get_index_definitions(
+    pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<Vec<IndexDefinition>, Box<dyn std::error::Error>> {
+    let rows = sqlx::query(&index_definitions_query(table, "")).fetch_all(pool).await?;
+    Ok(rows.iter().map(IndexDefinition::from_row).collect::<Result<_, _>>()?)
+}
+
+/// The indexes and unique constraints of `table` on the Replica that contain the column
+/// `column_id`, as key or included column.
+async fn get_dependent_indexes(
+    conn: &mut MssqlConnection,
+    table: &TableRef,
+    column_id: i32,
+) -> Result<Vec<IndexDefinition>, Box<dyn std::error::Error>> {
+    let filter = format!(
+        "AND i.is_primary_key = 0
+         AND EXISTS (SELECT 1 FROM sys.index_columns ic
+                     WHERE ic.object_id = i.object_id AND ic.index_id = i.index_id AND ic.column_id = {})",
+        column_id
+    );
+    let rows = sqlx::query(&index_definitions_query(table, &filter)).fetch_all(&mut *conn).await?;
+    Ok(rows.iter().map(IndexDefinition::from_row).collect::<Result<_, _>>()?)
+}
+
+fn index_definitions_query(table: &TableRef, filter: &str) -> String {
+    format!(
+        "SELECT {}
+         FROM sys.indexes i
+         LEFT JOIN sys.xml_indexes xi ON xi.object_id = i.object_id AND xi.index_id = i.index_id
+         LEFT JOIN sys.spatial_index_tessellations st ON st.object_id = i.object_id AND st.index_id = i.index_id
+         WHERE i.object_id = {}
+         AND i.type > 0
+         AND i.is_hypothetical = 0 {}",
+        indexes::DEFINITION_SELECT, table.object_id(), filter
+    )
+}
+
+/// Reseeds the identity of `table` on the Replica to the Primary's `IDENT_CURRENT`, so rows
+/// inserted after a promotion continue the Primary's sequence. Returns the new current value, or
+/// `None` if the table has no identity column.
+pub async fn reseed_identity(
+    primary: &mut sqlx::mssql::MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let current_query = format!(
+        "SELECT CAST(IDENT_CURRENT(N'{}') AS VARCHAR(40))",
+        table.quoted().replace('\'', "''")
+    );
+    let current: Option<String> = sqlx::query_scalar(&current_query).fetch_one(&mut *primary).await?;
+    let Some(current) = current.filter(|v| v.parse::<i128>().is_ok()) else {
+        return Ok(None);
+    };
+
+    let reseed_sql = format!(
+        "DBCC CHECKIDENT (N'{}', RESEED, {}) WITH NO_INFOMSGS",
+        table.quoted().replace('\'', "''"), current
+    );
+    sqlx::query(&reseed_sql).execute(replica_pool).await?;
+    Ok(Some(current))
+}
+
+/// (Re)creates the empty shadow copy of `table` on the Replica, with the same columns, indexes
+/// and constraints, for a shadow reload to load into.
+pub async fn create_shadow_table(
     primary_pool: &Pool<Mssql>,
     replica_pool: &Pool<Mssql>,
-    table_name: &str,
+    table: &TableRef,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // 1. Fetch Indexes & Unique Constraints
-    let idx_query = format!(
+    let shadow = table.shadow();
+    let drop_sql = format!("IF {} IS NOT NULL DROP TABLE {}", shadow.object_id(), shadow.quoted());
+    sqlx::query(&drop_sql).execute(replica_pool).await?;
+
+    let columns = get_column_definitions(primary_pool, table).await?;
+    if columns.is_empty() {
+        return Err(format!("Table {} not found on Primary", table).into());
+    }
+
+    info!("Creating shadow table {} for reload of {}", shadow, table);
+    create_table(primary_pool, replica_pool, table, &shadow, &columns).await
+}
+
+/// True if the shadow copy of `table` exists on the Replica.
+pub async fn shadow_table_exists(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let exists: Option<i32> = sqlx::query_scalar(&format!("SELECT {}", table.shadow().object_id()))
+        .fetch_one(replica_pool)
+        .await?;
+    Ok(exists.is_some())
+}
+
+/// Swaps the loaded shadow copy of `table` in for the live Replica table in one transaction and
+/// drops the old table. Foreign keys of other tables that reference the live table are
+/// recreated against the new one `WITH NOCHECK`, so the swap does not re-validate their rows.
+pub async fn swap_shadow_table(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shadow = table.shadow();
+    let retired = TableRef::new(&table.schema, &format!("{}__ct_old", table.name));
+
+    let inbound_fk_query = format!(
         "SELECT 
-            i.name as IndexName, 
-            CAST(i.is_unique AS BIT) as IsUnique,
-            CAST(i.is_unique_constraint AS BIT) as IsUniqueConstraint,
-            i.type_desc as TypeDesc,
+            fk.name AS ForeignKeyName,
+            OBJECT_SCHEMA_NAME(fk.parent_object_id) AS ParentSchemaName,
+            OBJECT_NAME(fk.parent_object_id) AS ParentTableName,
             CAST(STUFF((
-                SELECT ', [' + c.name + ']' + CASE WHEN ic.is_descending_key = 1 THEN ' DESC' ELSE '' END
-                FROM sys.index_columns ic
-                JOIN sys.columns c ON ic.object_id = c.object_id AND ic.column_id = c.column_id
-                WHERE ic.object_id = i.object_id AND ic.index_id = i.index_id
-                ORDER BY ic.key_ordinal
+                SELECT ', [' + c.name + ']'
+                FROM sys.foreign_key_columns fkc
+                JOIN sys.columns c ON fkc.parent_object_id = c.object_id AND fkc.parent_column_id = c.column_id
+                WHERE fkc.constraint_object_id = fk.object_id
+                ORDER BY fkc.constraint_column_id
                 FOR XML PATH('')
-            ), 1, 2, '') AS NVARCHAR(4000)) as Columns
-         FROM sys.indexes i
-         WHERE i.object_id = OBJECT_ID('{}') 
-         AND i.is_primary_key = 0 
-         AND i.type > 0",
-        table_name
+            ), 1, 2, '') AS NVARCHAR(4000)) AS ParentColumns,
+            CAST(STUFF((
+                SELECT ', [' + c.name + ']'
+                FROM sys.foreign_key_columns fkc
+                JOIN sys.columns c ON fkc.referenced_object_id = c.object_id AND fkc.referenced_column_id = c.column_id
+                WHERE fkc.constraint_object_id = fk.object_id
+                ORDER BY fkc.constraint_column_id
+                FOR XML PATH('')
+            ), 1, 2, '') AS NVARCHAR(4000)) AS ReferencedColumns,
+            fk.delete_referential_action_desc AS DeleteAction,
+            fk.update_referential_action_desc AS UpdateAction
+        FROM sys.foreign_keys fk
+        WHERE fk.referenced_object_id = {} AND fk.parent_object_id <> fk.referenced_object_id",
+        table.object_id()
+    );
+
+    let mut tx = replica_pool.begin().await?;
+    let inbound_fks = sqlx::query(&inbound_fk_query).fetch_all(&mut *tx).await?;
+
+    let mut recreate_fks = Vec::new();
+    for row in &inbound_fks {
+        let name: String = row.get("ForeignKeyName");
+        let parent = TableRef::new(&row.get::<String, _>("ParentSchemaName"), &row.get::<String, _>("ParentTableName"));
+        let p_cols: String = row.get("ParentColumns");
+        let r_cols: String = row.get("ReferencedColumns");
+
+        let drop_sql = format!("ALTER TABLE {} DROP CONSTRAINT [{}]", parent.quoted(), name);
+        sqlx::query(&drop_sql).execute(&mut *tx).await?;
+
+        recreate_fks.push(format!(
+            "ALTER TABLE {} WITH NOCHECK ADD CONSTRAINT [{}] FOREIGN KEY ({}) REFERENCES {} ({}){}",
+            parent.quoted(), name, p_cols, table.quoted(), r_cols,
+            referential_actions(row.try_get("DeleteAction").ok(), row.try_get("UpdateAction").ok())
+        ));
+    }
+
+    let swap_sql = format!(
+        "IF {} IS NOT NULL DROP TABLE {};
+         EXEC sp_rename N'{}', N'{}';
+         EXEC sp_rename N'{}', N'{}';
+         DROP TABLE {};",
+        retired.object_id(), retired.quoted(),
+        table.quoted().replace('\'', "''"), retired.name_literal(),
+        shadow.quoted().replace('\'', "''"), table.name_literal(),
+        retired.quoted()
+    );
+    sqlx::query(&swap_sql).execute(&mut *tx).await?;
+
+    // The old table's constraints are gone, so the shadow's can take over their names
+    let suffixed_query = format!(
+        "SELECT name FROM sys.objects WHERE parent_object_id = {} AND type IN ('UQ', 'F') AND RIGHT(name, {}) = N'{}'",
+        table.object_id(), SHADOW_SUFFIX.len(), SHADOW_SUFFIX
+    );
+    let suffixed: Vec<String> = sqlx::query_scalar(&suffixed_query).fetch_all(&mut *tx).await?;
+    for name in suffixed {
+        let original = &name[..name.len() - SHADOW_SUFFIX.len()];
+        let rename_sql = format!(
+            "EXEC sp_rename N'{}', N'{}', N'OBJECT'",
+            format!("[{}].[{}]", table.schema.replace(']', "]]"), name.replace(']', "]]")).replace('\'', "''"),
+            original.replace('\'', "''")
+        );
+        sqlx::query(&rename_sql).execute(&mut *tx).await?;
+    }
+
+    for create_sql in recreate_fks {
+        sqlx::query(&create_sql).execute(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+    info!("Swapped shadow table {} in for {}", shadow, table);
+    Ok(())
+}
+
+/// ` ON DELETE ... ON UPDATE ...` clauses for a foreign key's referential actions (empty for NO ACTION).
+fn referential_actions(delete_action: Option<String>, update_action: Option<String>) -> String {
+    let mut clauses = String::new();
+    if let Some(da) = delete_action {
+        let da_str = da.replace("_", " ");
+        if da_str != "NO ACTION" {
+            clauses.push_str(&format!(" ON DELETE {}", da_str));
+        }
+    }
+    if let Some(ua) = update_action {
+        let ua_str = ua.replace("_", " ");
+        if ua_str != "NO ACTION" {
+            clauses.push_str(&format!(" ON UPDATE {}", ua_str));
+        }
+    }
+    clauses
+}
+
+/// Creates `target` on the Replica with the columns, primary key, indexes and constraints of
+/// `source` on the Primary. `columns` are the definitions queried by `ensure_table_exists`.
+async fn create_table(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    source: &TableRef,
+    target: &TableRef,
+    columns: &[ColumnDefinition],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Non-dbo schemas have to exist before the table can be created in them
+    let create_schema_sql = format!(
+        "IF SCHEMA_ID(N'{}') IS NULL EXEC('CREATE SCHEMA [{}]')",
+        target.schema_literal(), target.schema.replace(']', "]]").replace('\'', "''")
+    );
+    sqlx::query(&create_schema_sql).execute(replica_pool).await?;
+
+    let mut create_sql = format!("CREATE TABLE {} (", target.quoted());
+
+    create_sql.push_str(&columns.iter().map(ColumnDefinition::render).collect::<Vec<_>>().join(", "));
+
+    // Get PK
+    let primary_key = get_index_definitions(primary_pool, source).await?
+        .into_iter()
+        .find(|i| i.is_primary_key);
+    if let Some(pk) = primary_key {
+        create_sql.push_str(&format!(", {}", pk.primary_key_clause()));
+    }
+
+    create_sql.push(')');
+
+    info!("Executing: {}", create_sql);
+    sqlx::query(&create_sql).execute(replica_pool).await?;
+    
+    let enable_ct_query = format!(
+        "ALTER TABLE {} ENABLE CHANGE_TRACKING WITH (TRACK_COLUMNS_UPDATED = ON)",
+        target.quoted()
+    );
+    let _ = sqlx::query(&enable_ct_query).execute(replica_pool).await;
+
+    // Initialize schema objects (Indexes, Unique constraints, Foreign keys) for the newly created table
+    info!("Initializing indexes and constraints for new table {}", target);
+    sync_schema_objects(primary_pool, replica_pool, source, target).await?;
+
+    Ok(())
+}
+
+/// Returns the primary key columns of a table in key ordinal order (empty if the table has no PK).
+pub async fn get_primary_key_columns<'e, E>(
+    executor: E,
+    table: &TableRef,
+) -> Result<Vec<String>, Box<dyn std::error::Error>>
+where
+    E: sqlx::Executor<'e, Database = Mssql>,
+{
+    let pk_query = format!(
+        "SELECT COLUMN_NAME 
+         FROM INFORMATION_SCHEMA.KEY_COLUMN_USAGE 
+         WHERE OBJECTPROPERTY(OBJECT_ID(QUOTENAME(CONSTRAINT_SCHEMA) + '.' + QUOTENAME(CONSTRAINT_NAME)), 'IsPrimaryKey') = 1 
+         AND TABLE_SCHEMA = '{}' AND TABLE_NAME = '{}'
+         ORDER BY ORDINAL_POSITION",
+        table.schema_literal(), table.name_literal()
     );
 
-    let p_indexes = sqlx::query(&idx_query).fetch_all(primary_pool).await?;
-    let r_indexes = sqlx::query(&idx_query).fetch_all(replica_pool).await?;
+    let pk_rows = sqlx::query(&pk_query).fetch_all(executor).await?;
+    Ok(pk_rows.iter().map(|row| row.get::<String, _>("COLUMN_NAME")).collect())
+}
+
+/// Columns that identify rows of a table that is not change-tracked: the `declared` replica key
+/// (a comma-separated column list, or `index:<name>` for a unique index), else the primary key,
+/// else the first unique, unfiltered index whose key columns are all NOT NULL. Empty if the
+/// table has no usable key. A declared key must name a unique index or NOT NULL columns; whether
+/// a column list is unique is checked against the rows by `snapshot::diff_keys`.
+pub async fn get_replica_key_columns(
+    conn: &mut sqlx::mssql::MssqlConnection,
+    table: &TableRef,
+    declared: Option<&str>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let index_filter = match declared.map(str::trim) {
+        Some(d) if d.starts_with("index:") => {
+            let index_name = d["index:".len()..].trim().replace('\'', "''");
+            let unique_query = format!(
+                "SELECT CAST(is_unique AS BIT) FROM sys.indexes WHERE object_id = {} AND name = N'{}'",
+                table.object_id(), index_name
+            );
+            match sqlx::query_scalar::<_, bool>(&unique_query).fetch_optional(&mut *conn).await? {
+                Some(true) => {}
+                Some(false) => return Err(format!("Replica key index {} of {} is not unique", index_name, table).into()),
+                None => return Err(format!("Replica key index {} of {} does not exist", index_name, table).into()),
+            }
+            format!("i.name = N'{}'", index_name)
+        }
+        Some(d) if !d.is_empty() => {
+            let key_cols: Vec<String> = d.split(',').map(|c| c.trim().trim_start_matches('[').trim_end_matches(']').to_string()).collect();
+            require_not_null(&mut *conn, table, &key_cols).await?;
+            return Ok(key_cols);
+        }
+        _ => {
+            let pk_cols = get_primary_key_columns(&mut *conn, table).await?;
+            if !pk_cols.is_empty() {
+                return Ok(pk_cols);
+            }
+            "i.is_unique = 1 AND i.has_filter = 0 AND NOT EXISTS (
+                SELECT 1 FROM sys.index_columns nic
+                JOIN sys.columns nc ON nic.object_id = nc.object_id AND nic.column_id = nc.column_id
+                WHERE nic.object_id = i.object_id AND nic.index_id = i.index_id
+                AND nic.is_included_column = 0 AND nc.is_nullable = 1
+            )".to_string()
+        }
+    };
+
+    let key_query = format!(
+        "SELECT c.name
+         FROM sys.index_columns ic
+         JOIN sys.columns c ON ic.object_id = c.object_id AND ic.column_id = c.column_id
+         WHERE ic.object_id = {} AND ic.is_included_column = 0 AND ic.index_id = (
+             SELECT TOP 1 i.index_id FROM sys.indexes i
+             WHERE i.object_id = {} AND i.type > 0 AND {}
+             ORDER BY i.index_id
+         )
+         ORDER BY ic.key_ordinal",
+        table.object_id(), table.object_id(), index_filter
+    );
+    let key_cols: Vec<String> = sqlx::query_scalar(&key_query).fetch_all(&mut *conn).await?;
+    if declared.is_some_and(|d| !d.trim().is_empty()) {
+        require_not_null(&mut *conn, table, &key_cols).await?;
+    }
+    Ok(key_cols)
+}
+
+/// Fails unless every one of `columns` exists on `table` and is NOT NULL, since a NULL key
+/// never matches its row.
+async fn require_not_null(
+    conn: &mut sqlx::mssql::MssqlConnection,
+    table: &TableRef,
+    columns: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for column in columns {
+        let nullable_query = format!(
+            "SELECT CAST(is_nullable AS BIT) FROM sys.columns WHERE object_id = {} AND name = N'{}'",
+            table.object_id(), column.replace('\'', "''")
+        );
+        match sqlx::query_scalar::<_, bool>(&nullable_query).fetch_optional(&mut *conn).await? {
+            Some(false) => {}
+            Some(true) => return Err(format!("Replica key column [{}] of {} allows NULL", column, table).into()),
+            None => return Err(format!("Replica key column [{}] of {} does not exist", column, table).into()),
+        }
+    }
+    Ok(())
+}
 
-    let p_idx_names: Vec<String> = p_indexes.iter().map(|r| r.get("IndexName")).collect();
-    let r_idx_names: Vec<String> = r_indexes.iter().map(|r| r.get("IndexName")).collect();
+/// Drops and recreates the indexes and unique constraints of an existing Replica table that
+/// differ from the Primary's, and creates the ones it lacks.
+async fn sync_indexes(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let p_indexes = get_index_definitions(primary_pool, table).await?;
+    let r_indexes = get_index_definitions(replica_pool, table).await?;
+    let (r_dropped, p_created) = indexes::sync_plan(&p_indexes, &r_indexes, "");
+    drop_indexes(replica_pool, table, &r_dropped).await;
+    create_indexes(replica_pool, table, &p_created).await;
+    Ok(())
+}
+
+async fn drop_indexes(replica_pool: &Pool<Mssql>, target: &TableRef, indexes: &[&IndexDefinition]) {
+    for index in indexes {
+        info!("Dropping index/constraint {} on table {}", index.name, target);
+        if let Err(e) = sqlx::query(&index.drop_sql(target)).execute(replica_pool).await {
+            log::warn!("Failed to drop index/constraint {}: {}", index.name, e);
+        }
+    }
+}
+
+async fn create_indexes(replica_pool: &Pool<Mssql>, target: &TableRef, indexes: &[(&IndexDefinition, String)]) {
+    for (index, name) in indexes {
+        info!("Creating index/constraint {} on table {}", name, target);
+        if let Err(e) = sqlx::query(&index.create_sql(target, name)).execute(replica_pool).await {
+            log::warn!("Failed to create index {}: {}", name, e);
+        }
+    }
+}
+
+/// Mirrors the indexes, unique constraints and foreign keys of `table` on the Primary onto
+/// `target` on the Replica. When `target` is a shadow table, its constraint names carry
+/// `SHADOW_SUFFIX` and self-references point at the shadow.
+async fn sync_schema_objects(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    target: &TableRef,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &target.to_string();
+    let suffix = if target == table { "" } else { SHADOW_SUFFIX };
+
+    // 1. Fetch Indexes & Unique Constraints (the primary key is created with the table)
+    let p_indexes = get_index_definitions(primary_pool, table).await?;
+    let r_indexes = get_index_definitions(replica_pool, target).await?;
+    let (r_dropped, p_created) = indexes::sync_plan(&p_indexes, &r_indexes, suffix);
+    for index in p_indexes.iter().filter(|i| !i.is_primary_key && !i.is_supported()) {
+        log::warn!("Index {} on table {} cannot be scripted (type {}); skipping", index.name, table_name, index.index_type);
+    }
 
     // 2. Fetch Foreign Keys
-    let fk_query = format!(
+    let fk_query = |t: &TableRef| format!(
         "SELECT 
             fk.name AS ForeignKeyName,
+            OBJECT_SCHEMA_NAME(fk.referenced_object_id) AS ReferencedSchemaName,
             OBJECT_NAME(fk.referenced_object_id) AS ReferencedTableName,
             CAST(STUFF((
                 SELECT ', [' + c.name + ']'
@@ -257,14 +695,16 @@ pub async fn sync_schema_objects(
             fk.delete_referential_action_desc AS DeleteAction,
             fk.update_referential_action_desc AS UpdateAction
         FROM sys.foreign_keys fk
-        WHERE fk.parent_object_id = OBJECT_ID('{}')",
-        table_name
+        WHERE fk.parent_object_id = {}",
+        t.object_id()
     );
 
-    let p_fks = sqlx::query(&fk_query).fetch_all(primary_pool).await?;
-    let r_fks = sqlx::query(&fk_query).fetch_all(replica_pool).await?;
+    let p_fks = sqlx::query(&fk_query(table)).fetch_all(primary_pool).await?;
+    let r_fks = sqlx::query(&fk_query(target)).fetch_all(replica_pool).await?;
 
-    let p_fk_names: Vec<String> = p_fks.iter().map(|r| r.get("ForeignKeyName")).collect();
+    let p_fk_names: Vec<String> = p_fks.iter()
+        .map(|r| format!("{}{}", r.get::<String, _>("ForeignKeyName"), suffix))
+        .collect();
     let r_fk_names: Vec<String> = r_fks.iter().map(|r| r.get("ForeignKeyName")).collect();
 
     // --- DROP MISSING OBJECTS ---
@@ -273,59 +713,24 @@ pub async fn sync_schema_objects(
         let name: String = r_row.get("ForeignKeyName");
         if !p_fk_names.contains(&name) {
             info!("Dropping Foreign Key {} on table {}", name, table_name);
-            let drop_sql = format!("ALTER TABLE [{}] DROP CONSTRAINT [{}]", table_name, name);
+            let drop_sql = format!("ALTER TABLE {} DROP CONSTRAINT [{}]", target.quoted(), name);
             if let Err(e) = sqlx::query(&drop_sql).execute(replica_pool).await {
                 log::warn!("Failed to drop foreign key {}: {}", name, e);
             }
         }
     }
 
-    // 4. Drop missing Indexes & Constraints
-    for r_row in &r_indexes {
-        let name: String = r_row.get("IndexName");
-        let is_unique_constraint: bool = r_row.get("IsUniqueConstraint");
-        
-        if !p_idx_names.contains(&name) {
-            info!("Dropping index/constraint {} on table {}", name, table_name);
-            let drop_sql = if is_unique_constraint {
-                format!("ALTER TABLE [{}] DROP CONSTRAINT [{}]", table_name, name)
-            } else {
-                format!("DROP INDEX [{}] ON [{}]", name, table_name)
-            };
-            if let Err(e) = sqlx::query(&drop_sql).execute(replica_pool).await {
-                log::warn!("Failed to drop index/constraint {}: {}", name, e);
-            }
-        }
-    }
+    // 4. Drop missing and changed Indexes & Constraints
+    drop_indexes(replica_pool, target, &r_dropped).await;
 
     // --- CREATE MISSING OBJECTS ---
-    // 5. Create missing Indexes / Unique Constraints
-    for p_row in &p_indexes {
-        let name: String = p_row.get("IndexName");
-        let is_unique: bool = p_row.get("IsUnique");
-        let is_unique_constraint: bool = p_row.get("IsUniqueConstraint");
-        let columns: Option<String> = p_row.try_get("Columns").ok();
-
-        if !r_idx_names.contains(&name) {
-            if let Some(cols) = columns {
-                info!("Creating index/constraint {} on table {}", name, table_name);
-                let create_sql = if is_unique_constraint {
-                    format!("ALTER TABLE [{}] ADD CONSTRAINT [{}] UNIQUE ({})", table_name, name, cols)
-                } else {
-                    let unique_str = if is_unique { "UNIQUE " } else { "" };
-                    format!("CREATE {}INDEX [{}] ON [{}] ({})", unique_str, name, table_name, cols)
-                };
-
-                if let Err(e) = sqlx::query(&create_sql).execute(replica_pool).await {
-                    log::warn!("Failed to create index {}: {}", name, e);
-                }
-            }
-        }
-    }
+    // 5. Create missing and dropped Indexes / Unique Constraints
+    create_indexes(replica_pool, target, &p_created).await;
 
     // 6. Create missing Foreign Keys
     for p_row in &p_fks {
-        let name: String = p_row.get("ForeignKeyName");
+        let name = format!("{}{}", p_row.get::<String, _>("ForeignKeyName"), suffix);
+        let ref_schema: Option<String> = p_row.try_get("ReferencedSchemaName").ok();
         let ref_table: Option<String> = p_row.try_get("ReferencedTableName").ok();
         let p_cols: Option<String> = p_row.try_get("ParentColumns").ok();
         let r_cols: Option<String> = p_row.try_get("ReferencedColumns").ok();
@@ -333,26 +738,15 @@ pub async fn sync_schema_objects(
         let upd_action: Option<String> = p_row.try_get("UpdateAction").ok();
 
         if !r_fk_names.contains(&name) {
-            if let (Some(rt), Some(pc), Some(rc)) = (ref_table, p_cols, r_cols) {
+            if let (Some(rs), Some(rt), Some(pc), Some(rc)) = (ref_schema, ref_table, p_cols, r_cols) {
                 info!("Creating Foreign Key {} on table {}", name, table_name);
-                let mut create_sql = format!(
-                    "ALTER TABLE [{}] ADD CONSTRAINT [{}] FOREIGN KEY ({}) REFERENCES [{}] ({})",
-                    table_name, name, pc, rt, rc
+                let referenced = TableRef::new(&rs, &rt);
+                let referenced = if &referenced == table { target.clone() } else { referenced };
+                let create_sql = format!(
+                    "ALTER TABLE {} ADD CONSTRAINT [{}] FOREIGN KEY ({}) REFERENCES {} ({}){}",
+                    target.quoted(), name, pc, referenced.quoted(), rc, referential_actions(del_action, upd_action)
                 );
 
-                if let Some(da) = del_action {
-                    let da_str = da.replace("_", " ");
-                    if da_str != "NO ACTION" {
-                        create_sql.push_str(&format!(" ON DELETE {}", da_str));
-                    }
-                }
-                if let Some(ua) = upd_action {
-                    let ua_str = ua.replace("_", " ");
-                    if ua_str != "NO ACTION" {
-                        create_sql.push_str(&format!(" ON UPDATE {}", ua_str));
-                    }
-                }
-
                 if let Err(e) = sqlx::query(&create_sql).execute(replica_pool).await {
                     log::warn!("Failed to create foreign key {} (referenced table might not exist yet): {}", name, e);
                 }
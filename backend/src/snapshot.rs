@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use futures::TryStreamExt;
+use sqlx::{Pool, Mssql, Row};
+use sqlx::mssql::{MssqlConnection, MssqlRow};
+use crate::checkpoint;
+use crate::lob;
+use crate::sync::{build_insert_sql, decode_key, insert_rows, key_select_list};
+use crate::table::TableRef;
+use crate::triggers::{self, TriggerMode};
+use crate::value::{self, ColumnSpec, SqlValue};
+
+// Tables without a primary key cannot be change-tracked, so they are kept in sync by
+// periodically comparing a hash of every row between the Primary and the Replica.
+
+/// SHA-256 of a lossless XML rendering of the row (columns of the table aliased `t`), as hex.
+pub(crate) fn row_hash_expr(columns: &[&ColumnSpec]) -> String {
+    let cols = columns.iter().map(|c| format!("t.{}", c.quoted())).collect::<Vec<_>>().join(", ");
+    format!(
+        "CONVERT(VARCHAR(64), HASHBYTES('SHA2_256', CAST((SELECT {} FOR XML RAW, BINARY BASE64) AS NVARCHAR(MAX))), 2)",
+        cols
+    )
+}
+
+/// A row's typed key and hash, indexed by the key's text form.
+type KeyedHash = (Vec<SqlValue>, String);
+
+/// Compares a table that has a replica key row by row. Returns the keys to delete from
+/// `replica_table` and the keys whose rows must be copied from the Primary.
+pub async fn diff_keys(
+    primary: &mut MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    replica_table: &TableRef,
+    key_columns: &[&ColumnSpec],
+    columns: &[&ColumnSpec],
+) -> Result<(Vec<Vec<SqlValue>>, Vec<Vec<SqlValue>>), Box<dyn std::error::Error>> {
+    let hash_query = |t: &TableRef| format!(
+        "SELECT {}, {} AS [__ct_hash] FROM {} AS t",
+        key_select_list(key_columns), row_hash_expr(columns), t.quoted()
+    );
+    // Rows are matched on the text form of their typed key, which is exact for every key type
+    // A key that is NULL or not unique would silently drop rows from the comparison
+    let read_hashes = |rows: Vec<MssqlRow>| -> Result<HashMap<Vec<String>, KeyedHash>, Box<dyn std::error::Error>> {
+        let mut hashes = HashMap::with_capacity(rows.len());
+        for row in &rows {
+            let key = decode_key(row, key_columns)?;
+            let key_text = key.iter().map(value::key_to_text).collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("The replica key of {} is NULL in a row; choose a NOT NULL key", table))?;
+            if let Some((key, _)) = hashes.insert(key_text, (key, row.try_get::<String, _>("__ct_hash")?)) {
+                return Err(format!("The replica key of {} is not unique (duplicate key {:?}); choose a unique key", table, key).into());
+            }
+        }
+        Ok(hashes)
+    };
+
+    let primary_rows = read_hashes(sqlx::query(&hash_query(table)).fetch_all(&mut *primary).await?)?;
+    let replica_rows = read_hashes(sqlx::query(&hash_query(replica_table)).fetch_all(replica_pool).await?)?;
+
+    let upsert_keys = primary_rows.iter()
+        .filter(|(key_text, (_, hash))| replica_rows.get(*key_text).map(|(_, h)| h) != Some(hash))
+        .map(|(_, (key, _))| key.clone())
+        .collect();
+    let delete_keys = replica_rows.iter()
+        .filter(|(key_text, _)| !primary_rows.contains_key(*key_text))
+        .map(|(_, (key, _))| key.clone())
+        .collect();
+
+    Ok((delete_keys, upsert_keys))
+}
+
+/// Hashes staged per statement when surplus rows are deleted.
+const HASHES_PER_STATEMENT: usize = 1000;
+
+/// What `apply_row_diff` changes on the Replica for a table without any usable key.
+pub struct RowDiff {
+    /// Row hashes with the number of copies to delete from the Replica
+    surplus: Vec<(String, i64)>,
+    /// Decoded rows to insert into the Replica
+    missing: Vec<Vec<SqlValue>>,
+}
+
+impl RowDiff {
+    pub fn is_empty(&self) -> bool {
+        self.surplus.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Compares a table without any usable key by how often each distinct row occurs on both sides:
+/// surplus copies are to be deleted from `replica_table` and missing copies to be inserted from
+/// the Primary.
+pub async fn diff_rows(
+    primary: &mut MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    replica_table: &TableRef,
+    columns: &[&ColumnSpec],
+) -> Result<RowDiff, Box<dyn std::error::Error>> {
+    let hash = row_hash_expr(columns);
+    let count_query = |t: &TableRef| format!(
+        "SELECT h AS [__ct_hash], CAST(COUNT_BIG(*) AS BIGINT) AS [__ct_count] FROM (SELECT {} AS h FROM {} AS t) AS r GROUP BY h",
+        hash, t.quoted()
+    );
+    let read_counts = |rows: Vec<MssqlRow>| -> Result<HashMap<String, i64>, sqlx::Error> {
+        rows.iter()
+            .map(|row| Ok((row.try_get::<String, _>("__ct_hash")?, row.try_get::<i64, _>("__ct_count")?)))
+            .collect()
+    };
+
+    let primary_counts = read_counts(sqlx::query(&count_query(table)).fetch_all(&mut *primary).await?)?;
+    let replica_counts = read_counts(sqlx::query(&count_query(replica_table)).fetch_all(replica_pool).await?)?;
+
+    let surplus: Vec<(String, i64)> = replica_counts.iter()
+        .map(|(row_hash, &count)| (row_hash.clone(), count - primary_counts.get(row_hash).copied().unwrap_or(0)))
+        .filter(|(_, n)| *n > 0)
+        .collect();
+    let mut missing: HashMap<&String, i64> = primary_counts.iter()
+        .map(|(row_hash, &count)| (row_hash, count - replica_counts.get(row_hash).copied().unwrap_or(0)))
+        .filter(|(_, n)| *n > 0)
+        .collect();
+
+    // Missing copies are picked from one pass over the Primary's rows instead of a query per hash.
+    // Without a key a LOB value cannot be read back later, so it is read whole in that pass.
+    let mut rows = Vec::new();
+    if !missing.is_empty() {
+        let pieces = lob::max_pieces(&mut *primary, table, columns).await?;
+        let row_query = missing_rows_query(table, columns, &pieces)?;
+        let mut stream = sqlx::query(&row_query).fetch(&mut *primary);
+        while let Some(row) = stream.try_next().await? {
+            let row_hash: String = row.try_get("__ct_hash")?;
+            if let Some(n) = missing.get_mut(&row_hash).filter(|n| **n > 0) {
+                *n -= 1;
+                let mut values = value::decode_row(&row, columns)?;
+                lob::read_pieces(&row, columns, &pieces, &mut values)?;
+                rows.push(values);
+            }
+        }
+    }
+    lob::check_lengths(table, columns, &rows)?;
+
+    Ok(RowDiff { surplus, missing: rows })
+}
+
+/// Query that reads every row of a keyless table with its hash and its LOB values in `pieces`
+/// piece columns per column (see `lob::max_pieces`).
+fn missing_rows_query(table: &TableRef, columns: &[&ColumnSpec], pieces: &[usize]) -> Result<String, String> {
+    let select_list = columns.iter().map(|c| c.select_expr())
+        .chain(lob::piece_select_list(columns, pieces)?)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!("SELECT {} AS [__ct_hash], {} FROM {} AS t", row_hash_expr(columns), select_list, table.quoted()))
+}
+
+/// Applies a `RowDiff` to `replica_table` and records `version` for the table in the same
+/// transaction. Returns the number of rows deleted and inserted.
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_row_diff(
+    replica_pool: &Pool<Mssql>,
+    table: &TableRef,
+    replica_table: &TableRef,
+    columns: &[&ColumnSpec],
+    diff: RowDiff,
+    has_identity: bool,
+    trigger_mode: TriggerMode,
+    version: i64,
+) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let RowDiff { surplus, missing: rows } = diff;
+    let hash = row_hash_expr(columns);
+    let insert_sql = build_insert_sql(replica_table, columns, has_identity);
+    let mut deleted = 0;
+    let mut tx = replica_pool.begin().await?;
+    let suppression = triggers::suppress(&mut tx, replica_table, trigger_mode).await?;
+
+    // Surplus copies are staged with their counts and deleted in one pass over the Replica's
+    // rows. Runs as plain batches (no parameters), so the temp table lives for the session.
+    if !surplus.is_empty() {
+        sqlx::query(
+            "IF OBJECT_ID('tempdb..#ct_surplus') IS NOT NULL DROP TABLE #ct_surplus;
+             CREATE TABLE #ct_surplus (h VARCHAR(64) COLLATE DATABASE_DEFAULT PRIMARY KEY, n BIGINT NOT NULL);"
+        )
+            .execute(&mut *tx)
+            .await?;
+        for chunk in surplus.chunks(HASHES_PER_STATEMENT) {
+            // Hashes are hex digits only, so they can be inlined
+            let values = chunk.iter().map(|(h, n)| format!("('{}', {})", h, n)).collect::<Vec<_>>().join(", ");
+            sqlx::query(&format!("INSERT INTO #ct_surplus (h, n) VALUES {};", values)).execute(&mut *tx).await?;
+        }
+        let del_sql = format!(
+            "WITH r AS (
+                SELECT h, ROW_NUMBER() OVER (PARTITION BY h ORDER BY (SELECT NULL)) AS rn
+                FROM (SELECT {} AS h FROM {} AS t) AS d
+             )
+             DELETE r FROM r JOIN #ct_surplus AS s ON s.h = r.h WHERE r.rn <= s.n;
+             DROP TABLE #ct_surplus;",
+            hash, replica_table.quoted()
+        );
+        deleted = sqlx::query(&del_sql).execute(&mut *tx).await?.rows_affected();
+    }
+
+    let inserted = rows.len() as u64;
+    insert_rows(&mut tx, &insert_sql, columns, rows).await?;
+
+    if has_identity {
+        let disable_identity = format!("SET IDENTITY_INSERT {} OFF;", replica_table.quoted());
+        let _ = sqlx::query(&disable_identity).execute(&mut *tx).await;
+    }
+
+    triggers::restore(&mut tx, replica_table, &suppression).await?;
+    checkpoint::set_version(&mut tx, table, version).await?;
+    tx.commit().await?;
+    Ok((deleted, inserted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_long_lob_values_of_keyless_tables_with_their_row() {
+        let line = ColumnSpec::new("Line", "int", None).unwrap();
+        let body = ColumnSpec::new("Body", "nvarchar", Some(-1)).unwrap();
+        let query = missing_rows_query(&TableRef::new("dbo", "AuditLog"), &[&line, &body], &[0, 2]).unwrap();
+
+        assert!(query.starts_with("SELECT CONVERT(VARCHAR(64), HASHBYTES('SHA2_256'"));
+        assert!(query.contains("[Line], CAST(DATALENGTH(CAST([Body] AS NVARCHAR(MAX))) / 2 AS BIGINT) AS [Body], "));
+        assert!(query.contains("SUBSTRING(CAST(t.[Body] AS NVARCHAR(MAX)), 1, 4000) AS NVARCHAR(4000)) AS [__ct_piece_1_0], "));
+        assert!(query.ends_with("SUBSTRING(CAST(t.[Body] AS NVARCHAR(MAX)), 4001, 4000) AS NVARCHAR(4000)) AS [__ct_piece_1_1] FROM [dbo].[AuditLog] AS t"));
+    }
+}
@@ -1,4 +1,49 @@
+use std::collections::HashMap;
 use redis::{Client, Commands, RedisResult};
+use crate::table::TableRef;
+
+/// Per-table key prefixes that existed before tables were schema-qualified.
+const UNQUALIFIED_KEY_PREFIXES: [&str; 4] = ["enabled", "force_full_load", "version", "progress"];
+
+/// Every per-table key prefix, keyed by the `[schema].[name]` form of the table.
+const TABLE_KEY_PREFIXES: [&str; 21] = [
+    "enabled", "force_full_load", "version", "progress", "needs_reload", "auto_reload",
+    "allow_narrowing_alters", "apply_mode", "full_load_checkpoint", "full_load_restart",
+    "reload_mode", "column_ids", "column_drop_policy", "column_missing_since", "replica_triggers",
+    "shadow_reload", "replica_key", "snapshot_diff_at", "reseed_identity", "identity_reseed_at",
+    "consistency_group",
+];
+
+/// Renames Redis keys written under earlier names of the table to its `[schema].[name]` form:
+/// the bare name of a `dbo` table (`mssql_sync:enabled:User`) and the dotted `schema.name` form
+/// (`mssql_sync:enabled:dbo.User`). Keys that already exist in the new form are left untouched.
+pub async fn migrate_legacy_keys(client: &Client, table: &TableRef) -> RedisResult<()> {
+    let mut renames = Vec::new();
+    for prefix in TABLE_KEY_PREFIXES {
+        let key = format!("mssql_sync:{}:{}", prefix, table);
+        renames.push((format!("mssql_sync:{}:{}", prefix, table.dotted()), key.clone()));
+        if table.schema == "dbo" && UNQUALIFIED_KEY_PREFIXES.contains(&prefix) {
+            renames.push((format!("mssql_sync:{}:{}", prefix, table.name), key));
+        }
+    }
+
+    let mut con = client.get_connection()?;
+    let mut exists_pipe = redis::pipe();
+    for (legacy_key, _) in &renames {
+        exists_pipe.exists(legacy_key);
+    }
+    let exists: Vec<bool> = exists_pipe.query(&mut con)?;
+
+    for ((legacy_key, key), _) in renames.iter().zip(exists).filter(|(_, exists)| *exists) {
+        // RENAMENX only moves the legacy key if the qualified key does not already exist;
+        // otherwise the qualified key wins and the stale legacy key is removed
+        let renamed: bool = redis::cmd("RENAMENX").arg(legacy_key).arg(key).query(&mut con)?;
+        if !renamed {
+            let _: () = con.del(legacy_key)?;
+        }
+    }
+    Ok(())
+}
 
 pub async fn get_last_version(client: &Client, table_name: &str) -> RedisResult<i64> {
     let mut con = client.get_connection()?;
@@ -28,6 +73,13 @@ pub async fn clear_force_full_load(client: &Client, table_name: &str) -> RedisRe
     Ok(())
 }
 
+pub async fn set_force_full_load(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:force_full_load:{}", table_name);
+    let _: () = con.set(key, "true")?;
+    Ok(())
+}
+
 pub async fn init_force_full_load(client: &Client, table_name: &str) -> RedisResult<()> {
     let mut con = client.get_connection()?;
     let key = format!("mssql_sync:force_full_load:{}", table_name);
@@ -70,3 +122,325 @@ pub async fn set_sync_progress(client: &Client, table_name: &str, synced: i64, t
     let _: () = con.set(key, progress_json)?;
     Ok(())
 }
+
+/// Flags a table whose change-tracking baseline is no longer valid and must be reloaded.
+pub async fn set_needs_reload(client: &Client, table_name: &str, reason: &str, last_version: i64, min_valid_version: i64) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:needs_reload:{}", table_name);
+    let detected_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let reload_json = serde_json::json!({
+        "reason": reason,
+        "lastVersion": last_version,
+        "minValidVersion": min_valid_version,
+        "detectedAt": detected_at as u64,
+    });
+    let _: () = con.set(key, reload_json.to_string())?;
+    Ok(())
+}
+
+pub async fn clear_needs_reload(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:needs_reload:{}", table_name);
+    let _: () = con.del(key)?;
+    Ok(())
+}
+
+/// Per-table override of the auto reload policy; falls back to `default` when unset.
+pub async fn is_auto_reload_enabled(client: &Client, table_name: &str, default: bool) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:auto_reload:{}", table_name);
+    let val: Option<String> = con.get(key)?;
+    Ok(val.map(|v| v == "true").unwrap_or(default))
+}
+
+/// Per-table override of whether column alterations that could lose data are applied; falls
+/// back to `default` when unset.
+pub async fn is_narrowing_alter_allowed(client: &Client, table_name: &str, default: bool) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:allow_narrowing_alters:{}", table_name);
+    let val: Option<String> = con.get(key)?;
+    Ok(val.map(|v| v == "true").unwrap_or(default))
+}
+
+/// Per-table apply mode override (`merge` or `delete_insert`); `None` when unset.
+pub async fn get_apply_mode(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:apply_mode:{}", table_name);
+    con.get(key)
+}
+
+/// Progress of an interrupted force full load, written after every committed page.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullLoadCheckpoint {
+    pub run_id: String,
+    /// Change tracking version the load started at; recorded as the table's version once it completes
+    pub snapshot_version: i64,
+    /// Primary key of the last committed row, in `value::key_to_text` form
+    pub last_key: Vec<String>,
+    pub rows_loaded: i64,
+    /// Whether the load writes to the shadow table of a shadow reload
+    #[serde(default)]
+    pub shadow: bool,
+}
+
+pub async fn get_full_load_checkpoint(client: &Client, table_name: &str) -> RedisResult<Option<FullLoadCheckpoint>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:full_load_checkpoint:{}", table_name);
+    let val: Option<String> = con.get(key)?;
+    Ok(val.and_then(|v| match serde_json::from_str(&v) {
+        Ok(checkpoint) => Some(checkpoint),
+        Err(e) => {
+            log::warn!("Ignoring unreadable full load checkpoint for {}: {}", table_name, e);
+            None
+        }
+    }))
+}
+
+pub async fn set_full_load_checkpoint(client: &Client, table_name: &str, checkpoint: &FullLoadCheckpoint) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:full_load_checkpoint:{}", table_name);
+    let checkpoint_json = serde_json::to_string(checkpoint).unwrap_or_default();
+    let _: () = con.set(key, checkpoint_json)?;
+    Ok(())
+}
+
+pub async fn clear_full_load_checkpoint(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:full_load_checkpoint:{}", table_name);
+    let _: () = con.del(key)?;
+    Ok(())
+}
+
+/// Reads and clears the request to restart the next full load from scratch instead of resuming.
+pub async fn take_full_load_restart(client: &Client, table_name: &str) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:full_load_restart:{}", table_name);
+    let val: Option<String> = con.get(&key)?;
+    if val.is_some() {
+        let _: () = con.del(&key)?;
+    }
+    Ok(val.as_deref() == Some("true"))
+}
+
+/// Per-table full load mode override (`truncate` or `shadow`); `None` when unset.
+pub async fn get_reload_mode(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:reload_mode:{}", table_name);
+    con.get(key)
+}
+
+/// The Primary's column names of the table by column id, as last seen by the schema poller.
+pub async fn get_column_names(client: &Client, table_name: &str) -> RedisResult<HashMap<i32, String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:column_ids:{}", table_name);
+    con.hgetall(key)
+}
+
+/// Replaces the stored column id to name map of the table.
+pub async fn set_column_names(client: &Client, table_name: &str, columns: &[(i32, String)]) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:column_ids:{}", table_name);
+    let _: () = redis::pipe()
+        .atomic()
+        .del(&key)
+        .hset_multiple(&key, columns)
+        .query(&mut con)?;
+    Ok(())
+}
+
+/// Per-table column drop policy override (`keep`, `rename` or `drop`); `None` when unset.
+pub async fn get_column_drop_policy(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:column_drop_policy:{}", table_name);
+    con.get(key)
+}
+
+/// Records `now` as the time `column` was first seen missing on the Primary, unless it already
+/// is, and returns the recorded time.
+pub async fn mark_column_missing(client: &Client, table_name: &str, column: &str, now: u64) -> RedisResult<u64> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:column_missing_since:{}", table_name);
+    let _: bool = con.hset_nx(&key, column, now)?;
+    con.hget(&key, column)
+}
+
+/// Forgets the missing-since times of every column not in `missing`.
+pub async fn clear_columns_missing(client: &Client, table_name: &str, missing: &[String]) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:column_missing_since:{}", table_name);
+    let recorded: Vec<String> = con.hkeys(&key)?;
+    for column in recorded.iter().filter(|c| !missing.contains(c)) {
+        let _: () = con.hdel(&key, column)?;
+    }
+    Ok(())
+}
+
+pub async fn get_replica_triggers(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:replica_triggers:{}", table_name);
+    con.get(key)
+}
+
+/// True while a loaded shadow table is catching up on changes before being swapped in.
+pub async fn is_shadow_reload_pending(client: &Client, table_name: &str) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:shadow_reload:{}", table_name);
+    let val: Option<String> = con.get(key)?;
+    Ok(val.as_deref() == Some("pending"))
+}
+
+pub async fn set_shadow_reload_pending(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:shadow_reload:{}", table_name);
+    let _: () = con.set(key, "pending")?;
+    Ok(())
+}
+
+pub async fn clear_shadow_reload_pending(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:shadow_reload:{}", table_name);
+    let _: () = con.del(key)?;
+    Ok(())
+}
+
+pub async fn set_full_load_restart(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:full_load_restart:{}", table_name);
+    let _: () = con.set(key, "true")?;
+    Ok(())
+}
+
+/// Declared replica key of a table without a primary key: a comma-separated column list, or
+/// `index:<name>` for a unique index. `None` when unset.
+pub async fn get_replica_key(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:replica_key:{}", table_name);
+    con.get(key)
+}
+
+/// True if the last snapshot diff of an untracked table is older than `interval_secs`.
+pub async fn is_snapshot_diff_due(client: &Client, table_name: &str, interval_secs: u64) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:snapshot_diff_at:{}", table_name);
+    let last: Option<u64> = con.get(key)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(last.is_none_or(|last| now.saturating_sub(last) >= interval_secs))
+}
+
+pub async fn set_snapshot_diff_at(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:snapshot_diff_at:{}", table_name);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let _: () = con.set(key, now)?;
+    Ok(())
+}
+
+/// Makes the table's next snapshot diff due immediately.
+pub async fn clear_snapshot_diff_at(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:snapshot_diff_at:{}", table_name);
+    let _: () = con.del(key)?;
+    Ok(())
+}
+
+/// Reads and clears an on-demand request to reseed the table's identity on the Replica.
+pub async fn take_identity_reseed(client: &Client, table_name: &str) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:reseed_identity:{}", table_name);
+    let val: Option<String> = con.get(&key)?;
+    if val.is_some() {
+        let _: () = con.del(&key)?;
+    }
+    Ok(val.as_deref() == Some("true"))
+}
+
+pub async fn request_identity_reseed(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:reseed_identity:{}", table_name);
+    let _: () = con.set(key, "true")?;
+    Ok(())
+}
+
+/// True if the table's identity was last reseeded more than `interval_secs` ago.
+pub async fn is_identity_reseed_due(client: &Client, table_name: &str, interval_secs: u64) -> RedisResult<bool> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:identity_reseed_at:{}", table_name);
+    let last: Option<u64> = con.get(key)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(last.is_none_or(|last| now.saturating_sub(last) >= interval_secs))
+}
+
+pub async fn set_identity_reseed_at(client: &Client, table_name: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:identity_reseed_at:{}", table_name);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let _: () = con.set(key, now)?;
+    Ok(())
+}
+
+/// Name of the consistency group the table is synced with, if any.
+pub async fn get_consistency_group(client: &Client, table_name: &str) -> RedisResult<Option<String>> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:consistency_group:{}", table_name);
+    let group: Option<String> = con.get(key)?;
+    Ok(group.map(|g| g.trim().to_string()).filter(|g| !g.is_empty()))
+}
+
+pub async fn set_group_watermark(client: &Client, group: &str, watermark: i64) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let key = format!("mssql_sync:group_watermark:{}", group);
+    let _: () = con.set(key, watermark)?;
+    Ok(())
+}
+
+/// A Replica foreign key switched to NOCHECK while its tables were applied, kept in the
+/// `mssql_sync:fk_nocheck` set until it has been re-validated.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NocheckForeignKey {
+    pub name: String,
+    pub table: TableRef,
+    pub referenced_table: TableRef,
+}
+
+pub async fn add_nocheck_foreign_key(client: &Client, fk: &NocheckForeignKey) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let fk_json = serde_json::to_string(fk).unwrap_or_default();
+    let _: () = con.sadd("mssql_sync:fk_nocheck", fk_json)?;
+    Ok(())
+}
+
+pub async fn get_nocheck_foreign_keys(client: &Client) -> RedisResult<Vec<NocheckForeignKey>> {
+    let mut con = client.get_connection()?;
+    let members: Vec<String> = con.smembers("mssql_sync:fk_nocheck")?;
+    Ok(members.iter().filter_map(|m| serde_json::from_str(m).ok()).collect())
+}
+
+pub async fn remove_nocheck_foreign_key(client: &Client, fk: &NocheckForeignKey) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let fk_json = serde_json::to_string(fk).unwrap_or_default();
+    let _: () = con.srem("mssql_sync:fk_nocheck", fk_json)?;
+    Ok(())
+}
+
+pub async fn set_cutover_report(client: &Client, report_json: &str) -> RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let _: () = con.set("mssql_sync:cutover_report", report_json)?;
+    Ok(())
+}
@@ -1,129 +1,696 @@
-use sqlx::{Pool, Mssql, Row, Column};
-use sqlx::mssql::MssqlRow;
+use sqlx::{Pool, Mssql, Row};
+use sqlx::mssql::{MssqlArguments, MssqlConnection, MssqlRow};
+use sqlx::pool::PoolConnection;
+use sqlx::query::Query;
 use redis::Client;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 use log::{info, debug};
 use crate::state;
 use crate::schema;
+use crate::table::TableRef;
+use crate::columns::ColumnDefinition;
+use crate::value::{self, ColumnSpec, SqlValue};
+use crate::lob;
+use crate::snapshot;
+use crate::checkpoint;
+use crate::loader::FullLoadLoader;
+use crate::foreign_keys::{self, ForeignKeyMode};
+use crate::triggers::{self, TriggerMode};
 
 use tokio_util::sync::CancellationToken;
 
+/// Service-wide sync behaviour, read once from the environment at startup.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Default for tables without a `mssql_sync:auto_reload:<table>` override: whether an
+    /// expired change-tracking baseline automatically schedules a force full load.
+    pub auto_reload_on_expiry: bool,
+    /// Default for tables without a `mssql_sync:apply_mode:<table>` override.
+    pub apply_mode: ApplyMode,
+    pub loader: FullLoadLoader,
+    /// Rows per full load page (`FULL_LOAD_CHUNK_SIZE`, default 5000).
+    pub full_load_chunk_size: usize,
+    /// Default for tables without a `mssql_sync:reload_mode:<table>` override.
+    pub reload_mode: ReloadMode,
+    pub foreign_key_mode: ForeignKeyMode,
+    /// Default for tables without a `mssql_sync:replica_triggers:<table>` override.
+    pub trigger_mode: TriggerMode,
+    /// Seconds between identity reseeds of the Replica tables (`IDENTITY_RESEED_INTERVAL_SECS`,
+    /// default 300; 0 only reseeds after full loads and on request).
+    pub identity_reseed_interval_secs: u64,
+    /// Changes read from `CHANGETABLE` and applied per batch (`CHANGE_BATCH_SIZE`, default 10000).
+    pub change_batch_size: usize,
+    /// Seconds between snapshot diffs of tables that are not change-tracked
+    /// (`SNAPSHOT_DIFF_INTERVAL_SECS`, default 300).
+    pub snapshot_diff_interval_secs: u64,
+    /// Default for tables without a `mssql_sync:allow_narrowing_alters:<table>` override: whether
+    /// column alterations that could lose data are applied to the Replica.
+    pub allow_narrowing_alters: bool,
+    /// Default for tables without a `mssql_sync:column_drop_policy:<table>` override.
+    pub column_drop_policy: ColumnDropPolicy,
+    /// Seconds a column has to be missing on the Primary before `ColumnDropPolicy::Drop` drops it
+    /// (`COLUMN_DROP_GRACE_SECS`, default 86400).
+    pub column_drop_grace_secs: u64,
+}
+
+impl SyncOptions {
+    pub fn from_env() -> Self {
+        let apply_mode = match std::env::var("APPLY_MODE") {
+            Ok(v) => ApplyMode::parse(&v).unwrap_or_else(|| {
+                log::warn!("Unknown APPLY_MODE '{}'; using delete_insert", v);
+                ApplyMode::DeleteInsert
+            }),
+            Err(_) => ApplyMode::DeleteInsert,
+        };
+        SyncOptions {
+            auto_reload_on_expiry: std::env::var("AUTO_RELOAD_ON_EXPIRY")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            apply_mode,
+            loader: FullLoadLoader::from_env(),
+            full_load_chunk_size: std::env::var("FULL_LOAD_CHUNK_SIZE")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(5000),
+            reload_mode: match std::env::var("RELOAD_MODE") {
+                Ok(v) => ReloadMode::parse(&v).unwrap_or_else(|| {
+                    log::warn!("Unknown RELOAD_MODE '{}'; using truncate", v);
+                    ReloadMode::Truncate
+                }),
+                Err(_) => ReloadMode::Truncate,
+            },
+            foreign_key_mode: ForeignKeyMode::from_env(),
+            trigger_mode: match std::env::var("REPLICA_TRIGGERS") {
+                Ok(v) => TriggerMode::parse(&v).unwrap_or_else(|| {
+                    log::warn!("Unknown REPLICA_TRIGGERS '{}'; using guard", v);
+                    TriggerMode::Guard
+                }),
+                Err(_) => TriggerMode::Guard,
+            },
+            identity_reseed_interval_secs: std::env::var("IDENTITY_RESEED_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(300),
+            change_batch_size: std::env::var("CHANGE_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(10000),
+            snapshot_diff_interval_secs: std::env::var("SNAPSHOT_DIFF_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(300),
+            allow_narrowing_alters: std::env::var("ALLOW_NARROWING_ALTERS")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            column_drop_policy: match std::env::var("COLUMN_DROP_POLICY") {
+                Ok(v) => ColumnDropPolicy::parse(&v).unwrap_or_else(|| {
+                    log::warn!("Unknown COLUMN_DROP_POLICY '{}'; using keep", v);
+                    ColumnDropPolicy::Keep
+                }),
+                Err(_) => ColumnDropPolicy::Keep,
+            },
+            column_drop_grace_secs: std::env::var("COLUMN_DROP_GRACE_SECS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(86400),
+        }
+    }
+}
+
+/// Where a force full load writes while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadMode {
+    /// TRUNCATE the live Replica table and load it in place
+    Truncate,
+    /// Load a shadow copy (`<table>__ct_reload`), catch it up on changes, then swap it in
+    Shadow,
+}
+
+impl ReloadMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "truncate" => Some(ReloadMode::Truncate),
+            "shadow" => Some(ReloadMode::Shadow),
+            _ => None,
+        }
+    }
+}
+
+/// Marks Replica columns renamed by `ColumnDropPolicy::Rename`, followed by the Unix time of the
+/// rename.
+const DROPPED_COLUMN_MARKER: &str = "__dropped_";
+
+/// What happens to Replica columns that no longer exist on the Primary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnDropPolicy {
+    /// Leave the column in place
+    Keep,
+    /// Rename the column to `<column>__dropped_<unix time>`
+    Rename,
+    /// Drop the column once it has been missing for the grace period
+    Drop,
+}
+
+impl ColumnDropPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "keep" => Some(ColumnDropPolicy::Keep),
+            "rename" => Some(ColumnDropPolicy::Rename),
+            "drop" => Some(ColumnDropPolicy::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// How incremental inserts, full-row updates and deletes are written to the Replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyMode {
+    /// DELETE the changed keys, then re-INSERT the current rows
+    DeleteInsert,
+    /// Stage changed rows and tombstones in `#ct_merge` and apply them with one MERGE per chunk
+    Merge,
+}
+
+impl ApplyMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "delete_insert" => Some(ApplyMode::DeleteInsert),
+            "merge" => Some(ApplyMode::Merge),
+            _ => None,
+        }
+    }
+}
+
 pub async fn run_single_table_sync(
     primary_pool: &Pool<Mssql>,
     replica_pool: &Pool<Mssql>,
     redis_client: &Client,
-    table_name: &str,
+    table: &TableRef,
+    options: &SyncOptions,
     cancel_token: CancellationToken
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+    if !prepare_table(primary_pool, replica_pool, redis_client, table, options).await? {
+        return Ok(());
+    }
+
+    // Sync data. All primary reads for the table share one connection so they can run
+    // inside a single SNAPSHOT transaction and observe the same point in time.
+    let mut primary_conn = primary_pool.acquire().await?;
+    let snapshot = begin_snapshot(&mut primary_conn).await?;
+
+    let result = sync_table(&mut primary_conn, primary_pool, replica_pool, redis_client, table, None, snapshot, options, cancel_token)
+        .await
+        .map_err(|e| format!("Sync error on {}: {}", table_name, e));
+
+    close_snapshot(primary_conn, snapshot, table_name).await;
+
+    result?;
+
+    Ok(())
+}
+
+/// Syncs the members of a consistency group one after another inside a single snapshot, all up
+/// to the same target version, then records the version every member has reached as the group's
+/// watermark.
+pub async fn run_group_sync(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    group: &str,
+    tables: &[TableRef],
+    options: &SyncOptions,
+    cancel_token: CancellationToken
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut members = Vec::new();
+    for table in tables {
+        if prepare_table(primary_pool, replica_pool, redis_client, table, options).await? {
+            members.push(table);
+        }
+    }
+    if members.is_empty() {
+        return Ok(());
+    }
+
+    let mut primary_conn = primary_pool.acquire().await?;
+    let snapshot = begin_snapshot(&mut primary_conn).await?;
+
+    let result: Result<(), String> = async {
+        let target_version: i64 = sqlx::query_scalar("SELECT CHANGE_TRACKING_CURRENT_VERSION()")
+            .fetch_one(&mut *primary_conn)
+            .await
+            .unwrap_or(0);
+        info!("Syncing consistency group {} ({} tables) to v{}", group, members.len(), target_version);
+
+        // Each member pass deletes before it upserts, but only within its own table. With enforced
+        // foreign keys the group's deletes therefore run first, child tables before the tables
+        // they reference, and the member passes follow parents first.
+        let dependencies = foreign_keys::get_dependencies(primary_pool).await.map_err(|e| e.to_string())?;
+        let members = foreign_keys::dependency_order(members.iter().map(|t| (*t).clone()).collect(), &dependencies);
+        if options.foreign_key_mode == ForeignKeyMode::Enforce && members.len() > 1 {
+            for table in foreign_keys::delete_order(&members, &dependencies) {
+                if cancel_token.is_cancelled() {
+                    return Ok(());
+                }
+                apply_pending_deletes(&mut primary_conn, replica_pool, redis_client, &table, target_version, options)
+                    .await
+                    .map_err(|e| format!("Sync error on {} (group {}): {}", table, group, e))?;
+            }
+        }
+
+        for table in &members {
+            if cancel_token.is_cancelled() {
+                break;
+            }
+            sync_table(&mut primary_conn, primary_pool, replica_pool, redis_client, table, Some(target_version), snapshot, options, cancel_token.clone())
+                .await
+                .map_err(|e| format!("Sync error on {} (group {}): {}", table, group, e))?;
+        }
+        Ok(())
+    }.await;
+
+    close_snapshot(primary_conn, snapshot, group).await;
+
+    // Members that failed or were cancelled hold the watermark back until they catch up
+    let mut watermark = i64::MAX;
+    for table in &members {
+        let version = match checkpoint::get_version(replica_pool, table).await? {
+            Some(version) => version,
+            None => state::get_last_version(redis_client, &table.to_string()).await?,
+        };
+        watermark = watermark.min(version);
+    }
+    checkpoint::set_group_watermark(replica_pool, group, watermark).await?;
+    if let Err(e) = state::set_group_watermark(redis_client, group, watermark).await {
+        log::warn!("Failed to mirror watermark of group {} to Redis: {}", group, e);
+    }
+
+    result?;
+
+    Ok(())
+}
+
+/// Deletes the rows `table` has lost on the Primary up to `target_version` from the Replica,
+/// without recording a version; the table's own pass applies the same changes again. Tables
+/// that are reloading or synced by snapshot diff are left to their pass.
+async fn apply_pending_deletes(
+    primary: &mut MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+    target_version: i64,
+    options: &SyncOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+    if state::should_force_full_load(redis_client, table_name).await?
+        || state::is_shadow_reload_pending(redis_client, table_name).await?
+    {
+        return Ok(());
+    }
+    let last_version = match checkpoint::get_version(replica_pool, table).await? {
+        Some(version) => version,
+        None => state::get_last_version(redis_client, table_name).await?,
+    };
+    if target_version <= last_version {
+        return Ok(());
+    }
+
+    // Untracked tables have no change set, and an expired baseline is handled by the table's pass
+    let baseline_query = format!(
+        "SELECT CAST(CASE WHEN EXISTS (SELECT 1 FROM sys.change_tracking_tables WHERE object_id = {0})
+                AND CHANGE_TRACKING_MIN_VALID_VERSION({0}) <= @p1 THEN 1 ELSE 0 END AS BIT)",
+        table.object_id()
+    );
+    let valid: bool = sqlx::query_scalar(&baseline_query).bind(last_version).fetch_one(&mut *primary).await?;
+    if !valid {
+        return Ok(());
+    }
+
+    let columns = load_columns(&mut *primary, table).await?;
+    let pk_cols = schema::get_primary_key_columns(&mut *primary, table).await?;
+    let key_columns: Vec<&ColumnSpec> = pk_cols.iter().filter_map(|k| columns.iter().find(|c| &c.name == k)).collect();
+    if key_columns.is_empty() || key_columns.len() != pk_cols.len() {
+        return Ok(());
+    }
+
+    let deletes_query = format!(
+        "SELECT {} FROM CHANGETABLE(CHANGES {}, @p1) AS t WHERE t.SYS_CHANGE_OPERATION = 'D' AND t.SYS_CHANGE_VERSION <= @p2",
+        key_select_list(&key_columns), table.quoted()
+    );
+    let rows = sqlx::query(&deletes_query).bind(last_version).bind(target_version).fetch_all(&mut *primary).await?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let delete_pks = rows.iter().map(|row| decode_key(row, &key_columns)).collect::<Result<Vec<_>, _>>()?;
+
+    info!("Deleting {} rows of {} ahead of the tables it references", delete_pks.len(), table_name);
+    let mut tx = replica_pool.begin().await?;
+    let suppression = triggers::suppress(&mut tx, table, replica_trigger_mode(redis_client, table_name, options).await).await?;
+    for chunk in delete_pks.chunks(100) {
+        let del_sql = format!("DELETE t FROM {} AS t {}", table.quoted(), key_values_join(&key_columns, chunk)?);
+        sqlx::query(&del_sql).execute(&mut *tx).await?;
+    }
+    triggers::restore(&mut tx, table, &suppression).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The trigger mode of `table`: its Redis override, or `REPLICA_TRIGGERS`.
+async fn replica_trigger_mode(redis_client: &Client, table_name: &str, options: &SyncOptions) -> TriggerMode {
+    match state::get_replica_triggers(redis_client, table_name).await {
+        Ok(Some(v)) => TriggerMode::parse(&v).unwrap_or_else(|| {
+            log::warn!("Unknown replica trigger mode '{}' for {}; using {:?}", v, table_name, options.trigger_mode);
+            options.trigger_mode
+        }),
+        _ => options.trigger_mode,
+    }
+}
+
+/// Prepares a table for syncing: migrates legacy Redis keys, initializes its flags and creates it
+/// on the Replica. Returns whether the table is enabled.
+async fn prepare_table(
+    primary_pool: &Pool<Mssql>,
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+    options: &SyncOptions,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
     debug!("Processing table: {}", table_name);
 
+    // Carry over state written under the earlier names of the table
+    if let Err(e) = state::migrate_legacy_keys(redis_client, table).await {
+        log::warn!("Failed to migrate legacy Redis keys for {}: {}", table_name, e);
+    }
+    if let Err(e) = checkpoint::migrate_table_name(replica_pool, table).await {
+        log::warn!("Failed to migrate the recorded version of {}: {}", table_name, e);
+    }
+
     // 1. Initialize enabled flag in Redis if it doesn't exist
     if let Err(e) = state::init_table_enabled(redis_client, table_name).await {
         log::error!("Failed to initialize enabled flag for {}: {}", table_name, e);
-        return Ok(());
+        return Ok(false);
     }
     
     // Initialize force full load flag in Redis if it doesn't exist
     if let Err(e) = state::init_force_full_load(redis_client, table_name).await {
         log::error!("Failed to initialize force full load flag for {}: {}", table_name, e);
-        return Ok(());
+        return Ok(false);
     }
 
     // 2. Check if table synchronization is enabled
     let is_enabled = state::is_table_enabled(redis_client, table_name).await.unwrap_or(false);
     if !is_enabled {
         info!("Sync skipped for table: {} (mssql_sync:enabled:{} is not true)", table_name, table_name);
-        return Ok(());
+        return Ok(false);
     }
     
     // Ensure table exists on Replica
-    schema::ensure_table_exists(primary_pool, replica_pool, table_name)
+    let allow_narrowing = state::is_narrowing_alter_allowed(redis_client, table_name, options.allow_narrowing_alters)
+        .await
+        .unwrap_or(options.allow_narrowing_alters);
+    let previous_names = state::get_column_names(redis_client, table_name).await.unwrap_or_else(|e| {
+        log::warn!("Failed to read column ids of {}; renames are not detected this pass: {}", table_name, e);
+        HashMap::new()
+    });
+    let ensured = schema::ensure_table_exists(primary_pool, replica_pool, table, &previous_names, allow_narrowing)
         .await
         .map_err(|e| format!("Schema error on {}: {}", table_name, e))?;
 
-    // Sync data
-    sync_table(primary_pool, replica_pool, redis_client, table_name, cancel_token)
-        .await
-        .map_err(|e| format!("Sync error on {}: {}", table_name, e))?;
+    let column_names: Vec<(i32, String)> = ensured.columns.iter().map(|c| (c.column_id, c.name.clone())).collect();
+    if let Err(e) = state::set_column_names(redis_client, table_name, &column_names).await {
+        log::warn!("Failed to save column ids of {}: {}", table_name, e);
+    }
+
+    if let Err(e) = retire_columns(replica_pool, redis_client, table, &ensured.retired, options).await {
+        log::warn!("Failed to retire dropped columns of {}: {}", table_name, e);
+    }
+
+    Ok(true)
+}
+
+/// Applies the column drop policy to Replica columns that no longer exist on the Primary.
+async fn retire_columns(
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+    retired: &[ColumnDefinition],
+    options: &SyncOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+    let policy = match state::get_column_drop_policy(redis_client, table_name).await? {
+        Some(v) => ColumnDropPolicy::parse(&v).unwrap_or_else(|| {
+            log::warn!("Unknown column drop policy '{}' for {}; using {:?}", v, table_name, options.column_drop_policy);
+            options.column_drop_policy
+        }),
+        None => options.column_drop_policy,
+    };
+
+    // Columns that came back (or are gone) restart their grace period
+    let names: Vec<String> = retired.iter().map(|c| c.name.clone()).collect();
+    state::clear_columns_missing(redis_client, table_name, &names).await?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    for column in retired {
+        match policy {
+            ColumnDropPolicy::Keep => {
+                debug!("Keeping column {} of {}, which no longer exists on the Primary", column.name, table_name);
+            },
+            // Columns renamed by an earlier pass are kept as they are
+            ColumnDropPolicy::Rename if column.name.contains(DROPPED_COLUMN_MARKER) => {},
+            ColumnDropPolicy::Rename => {
+                let suffix = format!("{}{}", DROPPED_COLUMN_MARKER, now);
+                let prefix: String = column.name.chars().take(128 - suffix.chars().count()).collect();
+                let new_name = format!("{}{}", prefix, suffix);
+                info!("Column {} of {} no longer exists on the Primary; renaming it to {}", column.name, table_name, new_name);
+                schema::rename_column(replica_pool, table, &column.name, &new_name).await?;
+            },
+            ColumnDropPolicy::Drop => {
+                let missing_since = state::mark_column_missing(redis_client, table_name, &column.name, now).await?;
+                if now.saturating_sub(missing_since) < options.column_drop_grace_secs {
+                    debug!("Column {} of {} is missing on the Primary since {}; dropping after the grace period", column.name, table_name, missing_since);
+                    continue;
+                }
+                info!("Column {} of {} has been missing on the Primary for {}s; dropping it", column.name, table_name, now - missing_since);
+                schema::drop_column(replica_pool, table, column).await?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Ends the snapshot transaction opened by `begin_snapshot`, if any, before `conn` goes back to
+/// the pool.
+async fn close_snapshot(mut conn: PoolConnection<Mssql>, snapshot: bool, label: &str) {
+    if snapshot {
+        if let Err(e) = end_snapshot(&mut conn).await {
+            // Never hand a connection with an open snapshot transaction back to the pool
+            log::warn!("Failed to close snapshot transaction for {}: {}", label, e);
+            let _ = conn.detach();
+        }
+    }
+}
+
+static SNAPSHOT_FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
 
+/// Opens a SNAPSHOT isolation transaction on the given primary connection if the database has
+/// `ALLOW_SNAPSHOT_ISOLATION` enabled. Returns whether a snapshot transaction was started.
+async fn begin_snapshot(conn: &mut MssqlConnection) -> Result<bool, Box<dyn std::error::Error>> {
+    let snapshot_state: Option<u8> = sqlx::query_scalar(
+        "SELECT snapshot_isolation_state FROM sys.databases WHERE name = DB_NAME()"
+    )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    if snapshot_state != Some(1) {
+        if !SNAPSHOT_FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+            log::warn!(
+                "ALLOW_SNAPSHOT_ISOLATION is OFF on the Primary database; version, CHANGETABLE and row reads \
+                 will not share a consistent snapshot and rows may reflect changes newer than the recorded version. \
+                 Run ALTER DATABASE ... SET ALLOW_SNAPSHOT_ISOLATION ON to enable consistent reads."
+            );
+        }
+        return Ok(false);
+    }
+
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL SNAPSHOT; BEGIN TRANSACTION;")
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+/// Ends the read-only snapshot transaction and restores the session's default isolation level
+/// before the connection returns to the pool.
+async fn end_snapshot(conn: &mut MssqlConnection) -> Result<(), Box<dyn std::error::Error>> {
+    sqlx::query("IF @@TRANCOUNT > 0 COMMIT TRANSACTION; SET TRANSACTION ISOLATION LEVEL READ COMMITTED;")
+        .execute(&mut *conn)
+        .await?;
     Ok(())
 }
 
+/// Syncs one table up to `target_version`, or to the Primary's current version when it is `None`.
+/// `snapshot` tells whether `primary` reads inside a snapshot transaction.
+#[allow(clippy::too_many_arguments)]
 async fn sync_table(
+    primary: &mut MssqlConnection,
     primary_pool: &Pool<Mssql>,
     replica_pool: &Pool<Mssql>,
     redis_client: &Client,
-    table_name: &str,
+    table: &TableRef,
+    target_version: Option<i64>,
+    snapshot: bool,
+    options: &SyncOptions,
     cancel_token: CancellationToken
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // 2. Get current version from Primary
-    let current_ver_query = "SELECT CHANGE_TRACKING_CURRENT_VERSION()";
-    let current_version: i64 = sqlx::query_scalar(current_ver_query)
-        .fetch_one(primary_pool)
-        .await
-        .unwrap_or(0); // If None (no changes ever), default 0
+    let table_name = &table.to_string();
 
-    // 3. Get last synced version from Redis
-    let last_version = state::get_last_version(redis_client, table_name).await?;
+    // 2. Get current version from Primary (first read, so it pins the snapshot when one is active).
+    // Members of a consistency group share the version read once for the whole group.
+    let current_version: i64 = match target_version {
+        Some(version) => version,
+        None => {
+            let current_ver_query = "SELECT CHANGE_TRACKING_CURRENT_VERSION()";
+            sqlx::query_scalar(current_ver_query)
+                .fetch_one(&mut *primary)
+                .await
+                .unwrap_or(0) // If None (no changes ever), default 0
+        }
+    };
+
+    // 3. Get last synced version from the Replica, falling back to Redis for tables whose version
+    // has not been recorded there yet (e.g. versions set by hand after restoring a backup)
+    let last_version = match checkpoint::get_version(replica_pool, table).await? {
+        Some(version) => version,
+        None => state::get_last_version(redis_client, table_name).await?,
+    };
 
     // Check for Force Full Load Flag
-    let force_full_load = state::should_force_full_load(redis_client, table_name).await.unwrap_or(false);
+    let mut force_full_load = state::should_force_full_load(redis_client, table_name).await.unwrap_or(false);
+
+    // A shadow reload that has finished loading catches up through the incremental path below,
+    // applied to the shadow table, and is swapped in once it is current
+    let mut shadow_pending = state::is_shadow_reload_pending(redis_client, table_name).await?;
+    if shadow_pending && !force_full_load {
+        // The reload was called off by clearing force_full_load; its shadow table is stale
+        state::clear_shadow_reload_pending(redis_client, table_name).await?;
+        shadow_pending = false;
+    }
+    if shadow_pending {
+        force_full_load = false;
+    }
+
+    // Tables without a primary key cannot be change-tracked; they are synced by snapshot diff
+    let tracked_query = format!(
+        "SELECT CAST(CASE WHEN EXISTS (SELECT 1 FROM sys.change_tracking_tables WHERE object_id = {}) THEN 1 ELSE 0 END AS BIT)",
+        table.object_id()
+    );
+    let tracked: bool = sqlx::query_scalar(&tracked_query).fetch_one(&mut *primary).await?;
 
     // Get Total Table Count
-    let total_count_query = format!("SELECT CAST(COUNT_BIG(*) AS BIGINT) FROM [{}]", table_name);
-    let total_records: i64 = sqlx::query_scalar(&total_count_query).fetch_one(primary_pool).await.unwrap_or(0);
+    let total_count_query = format!("SELECT CAST(COUNT_BIG(*) AS BIGINT) FROM {}", table.quoted());
+    let total_records: i64 = sqlx::query_scalar(&total_count_query).fetch_one(&mut *primary).await.unwrap_or(0);
     
     // Track execution startup time accurately from thread allocation
     let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
 
-    if !force_full_load && current_version <= last_version {
+    let up_to_date = if tracked {
+        current_version <= last_version
+    } else {
+        !state::is_snapshot_diff_due(redis_client, table_name, options.snapshot_diff_interval_secs).await?
+    };
+    if !force_full_load && up_to_date {
         // We are already fully synced
+        if shadow_pending {
+            finish_shadow_reload(replica_pool, redis_client, table).await?;
+        }
+        maintain_identity(&mut *primary, replica_pool, redis_client, table, options, false).await?;
         if let Err(e) = state::set_sync_progress(redis_client, table_name, total_records, total_records, started_at).await {
              log::warn!("Failed to store sync progress: {}", e);
         }
         return Ok(());
     }
 
+    // Validate the incremental baseline: CHANGETABLE silently returns an incomplete change set
+    // when the last synced version predates the retention window or the table was truncated
+    if !force_full_load && tracked {
+        let min_valid_query = format!("SELECT CHANGE_TRACKING_MIN_VALID_VERSION({})", table.object_id());
+        let min_valid_version: Option<i64> = sqlx::query_scalar(&min_valid_query)
+            .fetch_one(&mut *primary)
+            .await?;
+
+        let expired_baseline = min_valid_version.filter(|min_valid| last_version < *min_valid);
+        if let (Some(min_valid), true) = (expired_baseline, shadow_pending) {
+            log::warn!(
+                "Shadow table of {} fell behind the change retention window (minimum valid version {}) while catching up; reloading it from scratch",
+                table_name, min_valid
+            );
+            state::clear_shadow_reload_pending(redis_client, table_name).await?;
+            state::set_full_load_restart(redis_client, table_name).await?;
+            shadow_pending = false;
+            force_full_load = true;
+        } else if let Some(min_valid) = expired_baseline {
+            let reason = format!(
+                "Last synced version {} is older than the minimum valid version {} (change retention expired or table was truncated)",
+                last_version, min_valid
+            );
+            log::warn!("Invalid change tracking baseline for {}: {}", table_name, reason);
+            state::set_needs_reload(redis_client, table_name, &reason, last_version, min_valid).await?;
+
+            let auto_reload = state::is_auto_reload_enabled(redis_client, table_name, options.auto_reload_on_expiry)
+                .await
+                .unwrap_or(options.auto_reload_on_expiry);
+            if !auto_reload {
+                log::warn!("Incremental sync halted for {} until a full load is triggered (mssql_sync:force_full_load:{})", table_name, table_name);
+                return Ok(());
+            }
+
+            info!("Auto reload policy enabled for {}; scheduling force full load.", table_name);
+            state::set_force_full_load(redis_client, table_name).await?;
+            force_full_load = true;
+        }
+    }
+
     // Prepare Column List for SELECT (needed for both Full Load and Incremental)
-    // CAST decimal/numeric to avoid NumericN panic
-    let cols_query = format!(
-        "SELECT COLUMN_NAME, DATA_TYPE FROM INFORMATION_SCHEMA.COLUMNS WHERE TABLE_NAME = '{}' ORDER BY ORDINAL_POSITION",
-        table_name
-    );
-    let columns: Vec<(String, String)> = sqlx::query(&cols_query)
-        .map(|row: MssqlRow| (row.get("COLUMN_NAME"), row.get("DATA_TYPE")))
-        .fetch_all(primary_pool)
-        .await?;
+    // Each column is selected in a form its typed value can be decoded from without loss
+    let columns = load_columns(&mut *primary, table).await?;
+    let all_columns: Vec<&ColumnSpec> = columns.iter().collect();
         
-    let select_list = columns.iter().map(|(name, dtype)| {
-        if ["decimal", "numeric", "money", "smallmoney", "float", "real", "tinyint", "smallint", "int", "bigint", "bit"].contains(&dtype.to_lowercase().as_str()) {
-             // Cast to string to safely transport through sqlx (avoid NumericN panic and SQLx strict decoding panics)
-             // VARCHAR(100) fits any number representation and avoids sqlx LOB stream parsing bugs
-             format!("CAST([{}] AS VARCHAR(100)) AS [{}]", name, name) 
-        } else if ["datetime", "datetime2", "date", "time", "smalldatetime", "datetimeoffset"].contains(&dtype.to_lowercase().as_str()) {
-             // Cast to string to safely transport through sqlx (avoid DateTimeN panic)
-             format!("CONVERT(VARCHAR(100), [{}], 126) AS [{}]", name, name)
-        } else if ["text"].contains(&dtype.to_lowercase().as_str()) {
-             // Cast deprecated text to VARCHAR(8000) to avoid unsupported data type Text panic
-             // and avoid VARCHAR(MAX) which triggers sqlx LOB stream parsing bugs
-             format!("CAST([{}] AS VARCHAR(8000)) AS [{}]", name, name)
-        } else if ["ntext"].contains(&dtype.to_lowercase().as_str()) {
-             // Cast deprecated ntext to NVARCHAR(4000) to avoid unsupported data type NText panic
-             // and avoid NVARCHAR(MAX) which triggers sqlx LOB stream parsing bugs
-             format!("CAST([{}] AS NVARCHAR(4000)) AS [{}]", name, name)
-        } else {
-             format!("[{}]", name)
-        }
-    }).collect::<Vec<_>>().join(", ");
+    // Untracked tables are keyed by their declared replica key or a unique index instead
+    let pk_cols = if tracked {
+        schema::get_primary_key_columns(&mut *primary, table).await?
+    } else {
+        let declared = state::get_replica_key(redis_client, table_name).await?;
+        schema::get_replica_key_columns(&mut *primary, table, declared.as_deref()).await?
+    };
+    let key_columns: Vec<&ColumnSpec> = pk_cols.iter()
+        .map(|k| columns.iter().find(|c| &c.name == k)
+            .ok_or_else(|| format!("Key column [{}] of {} is not among its columns", k, table_name)))
+        .collect::<Result<_, _>>()?;
+
+    // Key columns are selected alongside each row so LOB values can be fetched for it
+    let mut select_exprs = Vec::new();
+    if !key_columns.is_empty() {
+        select_exprs.push(key_select_list(&key_columns));
+    }
+    select_exprs.extend(columns.iter().map(|c| c.select_expr()));
+    let select_list = select_exprs.join(", ");
 
 
     // --- IDENTITY CHECK ---
     let identity_check_query = format!(
-        "SELECT OBJECTPROPERTY(OBJECT_ID('{}'), 'TableHasIdentity')",
-        table_name
+        "SELECT OBJECTPROPERTY({}, 'TableHasIdentity')",
+        table.object_id()
     );
     let has_identity_val: Option<i32> = sqlx::query_scalar(&identity_check_query)
-        .fetch_optional(primary_pool)
+        .fetch_optional(&mut *primary)
         .await?;
     let has_identity = has_identity_val.unwrap_or(0) == 1;
     
@@ -134,97 +701,168 @@ async fn sync_table(
     let r_has_identity = replica_has_identity.unwrap_or(0) == 1;
     
     let has_identity = has_identity || r_has_identity;
+    let mut fk_disabled = false;
+    // Incremental writes go to the shadow table while a shadow reload catches up
+    let replica_table = if shadow_pending { table.shadow() } else { table.clone() };
+    let insert_sql = build_insert_sql(&replica_table, &all_columns, has_identity);
+
+    let trigger_mode = replica_trigger_mode(redis_client, table_name, options).await;
 
     // --- FORCE FULL LOAD LOGIC ---
     if force_full_load {
         info!("FORCE FULL LOAD detected for table: {}", table_name);
 
-        // 1. Truncate Replica
-        let truncate_sql = format!("TRUNCATE TABLE [{}]", table_name);
-        sqlx::query(&truncate_sql).execute(replica_pool).await?;
-        
-        // Find column for ORDER BY (required for OFFSET)
-        let pk_col_query = format!(
-            "SELECT COLUMN_NAME FROM INFORMATION_SCHEMA.KEY_COLUMN_USAGE 
-             WHERE OBJECTPROPERTY(OBJECT_ID(CONSTRAINT_SCHEMA + '.' + CONSTRAINT_NAME), 'IsPrimaryKey') = 1 
-             AND TABLE_NAME = '{}'", 
-            table_name
-        );
-        let pk_row = sqlx::query(&pk_col_query).fetch_optional(primary_pool).await?;
-        let order_col = match pk_row {
-            Some(row) => row.get::<String, _>("COLUMN_NAME"),
-            None => columns[0].0.clone(), // Fallback to first column
+        let reload_mode = match state::get_reload_mode(redis_client, table_name).await {
+            Ok(Some(v)) => ReloadMode::parse(&v).unwrap_or_else(|| {
+                log::warn!("Unknown reload mode '{}' for {}; using {:?}", v, table_name, options.reload_mode);
+                options.reload_mode
+            }),
+            _ => options.reload_mode,
         };
+        let shadow = reload_mode == ReloadMode::Shadow;
+        let load_table = if shadow { table.shadow() } else { table.clone() };
+
+        // Pages are walked in primary key order: each page starts after the last key of the
+        // previous one. Tables without a key fall back to OFFSET paging on the first column.
+        let key_indices: Vec<usize> = pk_cols.iter()
+            .filter_map(|k| columns.iter().position(|c| &c.name == k))
+            .collect();
+        let order_cols = if pk_cols.is_empty() { vec![columns[0].name.clone()] } else { pk_cols.clone() };
+        let order_by = order_cols.iter().map(|c| format!("t.[{}]", c.replace(']', "]]"))).collect::<Vec<_>>().join(", ");
 
         // 2. Chunked Full Load
-        let chunk_size = 5000;
+        let chunk_size = options.full_load_chunk_size;
         let mut offset = 0;
+        let mut last_key: Option<Vec<SqlValue>> = None;
         let mut total_inserted = 0;
+
+        // 1. Resume an interrupted load from its checkpoint, or truncate the Replica (or recreate
+        // the shadow table) and start over. Only keyed loads can resume, since OFFSET pages are
+        // not stable across runs.
+        let restart = state::take_full_load_restart(redis_client, table_name).await?;
+        let checkpoint = if restart || pk_cols.is_empty() {
+            None
+        } else {
+            state::get_full_load_checkpoint(redis_client, table_name).await?
+                .filter(|cp| cp.last_key.len() == key_columns.len() && cp.shadow == shadow)
+        };
+        let checkpoint = match checkpoint {
+            Some(_) if shadow && !schema::shadow_table_exists(replica_pool, table).await? => None,
+            checkpoint => checkpoint,
+        };
+
+        let (run_id, snapshot_version) = match checkpoint {
+            Some(cp) => {
+                let key = key_columns.iter().zip(&cp.last_key)
+                    .map(|(c, text)| value::key_from_text(c, text))
+                    .collect::<Result<Vec<_>, _>>()?;
+                info!(
+                    "Resuming full load {} for {} after {} rows (snapshot version {})",
+                    cp.run_id, table_name, cp.rows_loaded, cp.snapshot_version
+                );
+
+                // Rows committed after the checkpoint was last written are loaded again
+                let cleanup_sql = format!("DELETE t FROM {} AS t WHERE {}", load_table.quoted(), keyset_predicate(&key_columns, 0));
+                disable_foreign_keys(replica_pool, redis_client, &load_table, options, &mut fk_disabled).await?;
+                let mut tx = replica_pool.begin().await?;
+                let suppression = triggers::suppress(&mut tx, &load_table, trigger_mode).await?;
+                value::bind_row(sqlx::query(&cleanup_sql), &key_columns, key.clone())
+                    .execute(&mut *tx)
+                    .await?;
+                triggers::restore(&mut tx, &load_table, &suppression).await?;
+                tx.commit().await?;
+
+                last_key = Some(key);
+                total_inserted = cp.rows_loaded;
+                (cp.run_id, cp.snapshot_version)
+            }
+            None => {
+                state::clear_full_load_checkpoint(redis_client, table_name).await?;
+                if shadow {
+                    schema::create_shadow_table(primary_pool, replica_pool, table).await?;
+                } else {
+                    let truncate_sql = format!("TRUNCATE TABLE {}", table.quoted());
+                    sqlx::query(&truncate_sql).execute(replica_pool).await?;
+                }
+                (uuid::Uuid::new_v4().to_string(), current_version)
+            }
+        };
+        info!("Full load run {} for {} at snapshot version {}", run_id, table_name, snapshot_version);
+        let mut stage = options.loader.start(&all_columns).await.map_err(|e| e.to_string())?;
         
         loop {
             if cancel_token.is_cancelled() {
                 info!("Force load cancelled for {}; saving progress and aborting loop.", table_name);
                 break;
             }
-            let full_query = format!(
-                "SELECT {} FROM [{}] ORDER BY [{}] OFFSET {} ROWS FETCH NEXT {} ROWS ONLY", 
-                select_list, table_name, order_col, offset, chunk_size
-            );
-            
-            let rows = sqlx::query(&full_query).fetch_all(primary_pool).await?; 
-            let row_count = rows.len();
-            
-            if row_count == 0 {
-                break;
-            }
-            
-            // We use a Transaction to group thousands of single-row inserts for speed
-            // This avoids the 'os error 104' (connection reset by peer) caused by massive query strings
-            let mut tx = replica_pool.begin().await?;
-            
-            // Reusable string components for the query
-            let mut cols = Vec::new();
-            let mut placeholders = Vec::new();
-            for col in rows[0].columns() {
-                cols.push(format!("[{}]", col.name()));
-                placeholders.push(format!("@p{}", cols.len()));
-            }
-            
-            let insert_sql = if has_identity {
+            let full_query = if pk_cols.is_empty() {
                 format!(
-                    "SET IDENTITY_INSERT [{}] ON; INSERT INTO [{}] ({}) VALUES ({});",
-                     table_name, table_name, cols.join(", "), placeholders.join(", ")
+                    "SELECT {} FROM {} AS t ORDER BY {} OFFSET {} ROWS FETCH NEXT {} ROWS ONLY",
+                    select_list, table.quoted(), order_by, offset, chunk_size
                 )
             } else {
+                let after_key = match last_key {
+                    Some(_) => format!("WHERE {} ", keyset_predicate(&key_columns, 0)),
+                    None => String::new(),
+                };
                 format!(
-                    "INSERT INTO [{}] ({}) VALUES ({});",
-                     table_name, cols.join(", "), placeholders.join(", ")
+                    "SELECT TOP ({}) {} FROM {} AS t {}ORDER BY {}",
+                    chunk_size, select_list, table.quoted(), after_key, order_by
                 )
             };
+            let page_query = match last_key.take() {
+                Some(key) => value::bind_row(sqlx::query(&full_query), &key_columns, key),
+                None => sqlx::query(&full_query),
+            };
             
-            for row in rows {
-                let mut query_builder = sqlx::query(&insert_sql);
-                 
-                for col in row.columns() {
-                     let v: Option<String> = row.try_get(col.ordinal()).ok();
-                     query_builder = query_builder.bind(v);
-                }
-                 
-                if let Err(e) = query_builder.execute(&mut *tx).await {
-                    log::error!("Tx Insert Failed: {}", e);
-                    tx.rollback().await?;
-                    return Err(Box::new(e));
-                }
+            // Decode every value up front so a bad value fails the chunk before anything is written
+            let (_, decoded) = fetch_rows(&mut *primary, table, &key_columns, &all_columns, page_query).await?;
+            let row_count = decoded.len();
+            
+            if row_count == 0 {
+                break;
+            }
+            if let Some(last_row) = decoded.last() {
+                last_key = Some(key_indices.iter().map(|&i| last_row[i].clone()).collect());
+            }
+
+            // One transaction per page; the loader keeps each statement under SQL Server's
+            // parameter limit, which avoids the 'os error 104' caused by massive query strings
+            disable_foreign_keys(replica_pool, redis_client, &load_table, options, &mut fk_disabled).await?;
+            let mut tx = replica_pool.begin().await?;
+            let suppression = triggers::suppress(&mut tx, &load_table, trigger_mode).await?;
+            
+            if let Err(e) = options.loader.load(&mut tx, stage.as_mut(), &load_table, &all_columns, has_identity, decoded).await {
+                log::error!("Tx Insert Failed: {}", e);
+                tx.rollback().await?;
+                return Err(e);
             }
             
             if has_identity {
-                 let disable_identity = format!("SET IDENTITY_INSERT [{}] OFF;", table_name);
+                 let disable_identity = format!("SET IDENTITY_INSERT {} OFF;", load_table.quoted());
                  let _ = sqlx::query(&disable_identity).execute(&mut *tx).await;
             }
             
+            triggers::restore(&mut tx, &load_table, &suppression).await?;
             tx.commit().await?;
             
             total_inserted += row_count as i64;
+
+            // The checkpoint must not lag behind committed rows by more than one page, which the
+            // resume cleanup removes; failing to write it stops the load
+            if let Some(key) = &last_key {
+                if let Some(key_text) = key.iter().map(value::key_to_text).collect::<Option<Vec<_>>>() {
+                    let checkpoint = state::FullLoadCheckpoint {
+                        run_id: run_id.clone(),
+                        snapshot_version,
+                        last_key: key_text,
+                        rows_loaded: total_inserted,
+                        shadow,
+                    };
+                    state::set_full_load_checkpoint(redis_client, table_name, &checkpoint).await?;
+                }
+            }
+
             info!("Force Load Chunk: Table {} - Inserted {}/{} total rows", table_name, total_inserted, total_records);
             
             // Push Progress tracking to Redis!
@@ -239,11 +877,35 @@ async fn sync_table(
             }
         }
         
-        // 3. Update Sync Version
-        state::set_last_version(redis_client, table_name, current_version).await?;
+        if let Some(stage) = stage {
+            if let Err(e) = stage.close().await {
+                log::warn!("Failed to close the bulk load connection of {}: {}", table_name, e);
+            }
+        }
+
+        if cancel_token.is_cancelled() {
+            return Ok(());
+        }
+
+        // 3. Update Sync Version to the version the load started at, so changes made while a
+        // resumed load was interrupted are replayed by the incremental sync
+        checkpoint::set_version(&mut *replica_pool.acquire().await?, table, snapshot_version).await?;
+        state::set_last_version(redis_client, table_name, snapshot_version).await?;
+        state::clear_full_load_checkpoint(redis_client, table_name).await?;
+
+        if shadow {
+            // force_full_load stays set until the caught-up shadow table has been swapped in
+            state::set_shadow_reload_pending(redis_client, table_name).await?;
+            info!("Shadow load complete for table: {} (Total: {}); catching up before swap", table_name, total_inserted);
+            return Ok(());
+        }
         
-        // 4. Clear Flag
+        // 4. Clear Flag (and any pending reload request the full load has now satisfied)
         state::clear_force_full_load(redis_client, table_name).await?;
+        state::clear_needs_reload(redis_client, table_name).await?;
+
+        // Rows were loaded with IDENTITY_INSERT, which leaves the Replica's identity behind
+        maintain_identity(&mut *primary, replica_pool, redis_client, table, options, true).await?;
         
         info!("Force Full Load complete for table: {} (Total: {})", table_name, total_inserted);
         return Ok(());
@@ -251,157 +913,347 @@ async fn sync_table(
     // -----------------------------
 
 
-    info!("Syncing {} from v{} to v{}", table_name, last_version, current_version);
+    if tracked {
+        info!("Syncing {} from v{} to v{}", table_name, last_version, current_version);
+    } else {
+        info!("Comparing row hashes of {} (snapshot diff)...", table_name);
+    }
 
     // 4. Get Changes (Incremental Logic)
-    let pk_col_query = format!(
-        "SELECT COLUMN_NAME FROM INFORMATION_SCHEMA.KEY_COLUMN_USAGE 
-         WHERE OBJECTPROPERTY(OBJECT_ID(CONSTRAINT_SCHEMA + '.' + CONSTRAINT_NAME), 'IsPrimaryKey') = 1 
-         AND TABLE_NAME = '{}'", 
-        table_name
-    );
-    let pk_row = sqlx::query(&pk_col_query).fetch_optional(primary_pool).await?;
-    let pk_col = match pk_row {
-        Some(row) => row.get::<String, _>("COLUMN_NAME"),
-        None => return Ok(()), // Skip if no PK
+    if pk_cols.is_empty() {
+        if !tracked {
+            // No usable key: reconcile whole rows by how often each occurs on either side
+            let diff = snapshot::diff_rows(&mut *primary, replica_pool, table, &replica_table, &all_columns).await?;
+            if !diff.is_empty() {
+                disable_foreign_keys(replica_pool, redis_client, &replica_table, options, &mut fk_disabled).await?;
+            }
+            let (deleted, inserted) = snapshot::apply_row_diff(
+                replica_pool, table, &replica_table, &all_columns, diff, has_identity, trigger_mode, current_version
+            ).await?;
+            info!("Snapshot diff for {}: {} rows deleted, {} rows inserted", table_name, deleted, inserted);
+            state::set_snapshot_diff_at(redis_client, table_name).await?;
+            if let Err(e) = state::set_last_version(redis_client, table_name, current_version).await {
+                log::warn!("Failed to mirror version of {} to Redis: {}", table_name, e);
+            }
+        }
+        if shadow_pending {
+            finish_shadow_reload(replica_pool, redis_client, table).await?;
+        }
+        maintain_identity(&mut *primary, replica_pool, redis_client, table, options, false).await?;
+        if let Err(e) = state::set_sync_progress(redis_client, table_name, total_records, total_records, started_at).await {
+            log::warn!("Failed to set end-of-sync progress: {}", e);
+        }
+        return Ok(());
+    }
+
+    let apply_mode = match state::get_apply_mode(redis_client, table_name).await {
+        Ok(Some(v)) => ApplyMode::parse(&v).unwrap_or_else(|| {
+            log::warn!("Unknown apply mode '{}' for {}; using {:?}", v, table_name, options.apply_mode);
+            options.apply_mode
+        }),
+        _ => options.apply_mode,
     };
 
-    let changes_query = format!(
-        "SELECT 
-            ct.SYS_CHANGE_VERSION,
-            ct.SYS_CHANGE_OPERATION,
-            CAST(ct.[{}] AS NVARCHAR(4000)) AS pk_val_str
-         FROM CHANGETABLE(CHANGES dbo.[{}], @p1) AS ct
-         ORDER BY ct.SYS_CHANGE_VERSION",
-        pk_col, table_name
-    );
+    let merge_sql = if apply_mode == ApplyMode::Merge {
+        let identity_query = format!("SELECT name FROM sys.identity_columns WHERE object_id = {}", table.object_id());
+        let identity_cols: Vec<String> = sqlx::query_scalar(&identity_query).fetch_all(&mut *primary).await?;
+        Some(MergeSql::new(&replica_table, &all_columns, &pk_cols, &identity_cols, has_identity))
+    } else {
+        None
+    };
 
-    info!("Fetching CHANGETABLE for {}...", table_name);
-    let changes = sqlx::query(&changes_query)
-        .bind(last_version)
-        .fetch_all(primary_pool)
-        .await?;
+    // Every key is carried as its typed values, which match the row on either side exactly and
+    // let the next batch start after the last key of this one
+    let pk_select = key_select_list(&key_columns);
 
-    let mut delete_pks = std::collections::HashSet::new();
-    let mut upsert_pks = std::collections::HashSet::new();
+    // One '0'/'1' per table column from SYS_CHANGE_COLUMNS (TRACK_COLUMNS_UPDATED = ON).
+    // NULL for inserts, deletes, and updates without a usable mask, which copy the whole row.
+    let mask_select = columns.iter()
+        .map(|c| format!(
+            "CAST(CHANGE_TRACKING_IS_COLUMN_IN_MASK(COLUMNPROPERTY({}, N'{}', 'ColumnId'), t.SYS_CHANGE_COLUMNS) AS CHAR(1))",
+            table.object_id(), c.name.replace("'", "''")
+        ))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let order_by = key_columns.iter().map(|c| format!("t.{}", c.quoted())).collect::<Vec<_>>().join(", ");
 
-    for change in &changes {
-        let op: String = change.get("SYS_CHANGE_OPERATION");
-        let pk_val_str: String = change.get("pk_val_str"); 
+    // Changes are consumed in bounded batches ordered by (version, key). A key appears once in
+    // CHANGETABLE with its latest version, so batches never overlap and the stored version can
+    // advance after each one: to just below the last batch version while that version may have
+    // more rows, and to the current version once every change has been applied.
+    let batch_size = options.change_batch_size;
+    let mut synced_version = last_version;
+    let mut cursor: Option<(i64, Vec<SqlValue>)> = None;
 
-        // Safely escape single quotes for the IN clause
-        let safe_pk = pk_val_str.replace("'", "''");
+    loop {
+        let (delete_pks, upsert_pks, update_groups, batch_version, more) = if tracked {
+            let after_cursor = match cursor {
+                Some(_) => format!(
+                    "AND (t.SYS_CHANGE_VERSION > @p3 OR (t.SYS_CHANGE_VERSION = @p3 AND ({})))",
+                    keyset_predicate(&key_columns, 3)
+                ),
+                None => String::new(),
+            };
+            let changes_query = format!(
+                "SELECT TOP ({})
+                    t.SYS_CHANGE_VERSION,
+                    t.SYS_CHANGE_OPERATION,
+                    CASE WHEN t.SYS_CHANGE_OPERATION = 'U' AND t.SYS_CHANGE_COLUMNS IS NOT NULL
+                         THEN {} END AS changed_mask,
+                    {}
+                 FROM CHANGETABLE(CHANGES {}, @p1) AS t
+                 WHERE t.SYS_CHANGE_VERSION <= @p2 {}
+                 ORDER BY t.SYS_CHANGE_VERSION, {}",
+                batch_size, mask_select, pk_select, table.quoted(), after_cursor, order_by
+            );
 
-        match op.as_str() {
-            "D" => {
-                delete_pks.insert(safe_pk.clone());
-                upsert_pks.remove(&safe_pk);
-            },
-            "I" | "U" => {
-                upsert_pks.insert(safe_pk.clone());
-                delete_pks.remove(&safe_pk);
-            },
-            _ => {}
-        }
-    }
+            info!("Fetching CHANGETABLE batch for {} after v{}...", table_name, synced_version);
+            let mut changes_query = sqlx::query(&changes_query).bind(synced_version).bind(current_version);
+            if let Some((version, key)) = &cursor {
+                changes_query = value::bind_row(changes_query.bind(*version), &key_columns, key.clone());
+            }
+            let changes = changes_query.fetch_all(&mut *primary).await?;
 
-    let delete_pks: Vec<_> = delete_pks.into_iter().collect();
-    let upsert_pks: Vec<_> = upsert_pks.into_iter().collect();
+            let mut delete_pks = Vec::new();
+            let mut upsert_pks = Vec::new();
+            let mut update_groups: std::collections::HashMap<String, Vec<Vec<SqlValue>>> = std::collections::HashMap::new();
 
-    // Perform Bulk Deletes
-    for chunk in delete_pks.chunks(100) {
-        if cancel_token.is_cancelled() {
-            info!("Incremental sync cancelled for {}; aborting delete loop.", table_name);
-            break;
-        }
-        let in_clause = chunk.iter().map(|k| format!("'{}'", k)).collect::<Vec<_>>().join(",");
-        if !in_clause.is_empty() {
-            let del_sql = format!("DELETE FROM [{}] WHERE [{}] IN ({})", table_name, pk_col, in_clause);
-            info!("Executing bulk DELETE chunk for {} ({} items)...", table_name, chunk.len());
-            sqlx::query(&del_sql).execute(replica_pool).await?;
-        }
-    }
+            for change in &changes {
+                let op: String = change.get("SYS_CHANGE_OPERATION");
+                let changed_mask: Option<String> = change.try_get("changed_mask")?;
+                let key = decode_key(change, &key_columns)?;
 
-    // Perform Bulk Upserts
-    for chunk in upsert_pks.chunks(100) {
-        if cancel_token.is_cancelled() {
-            info!("Incremental sync cancelled for {}; aborting upsert loop.", table_name);
-            break;
-        }
-        let in_clause = chunk.iter().map(|k| format!("'{}'", k)).collect::<Vec<_>>().join(",");
-        if in_clause.is_empty() {
-            continue;
+                // Column-level updates are grouped by their mask so each group shares one UPDATE statement
+                match (op.as_str(), changed_mask) {
+                    ("D", _) => delete_pks.push(key),
+                    ("U", Some(mask)) => update_groups.entry(mask).or_default().push(key),
+                    ("I" | "U", _) => upsert_pks.push(key),
+                    _ => {}
+                }
+            }
+
+            let more = changes.len() == batch_size;
+            let batch_version = match changes.last() {
+                Some(last) if more => {
+                    let version: i64 = last.get("SYS_CHANGE_VERSION");
+                    cursor = Some((version, decode_key(last, &key_columns)?));
+                    version - 1
+                }
+                _ => current_version,
+            };
+            (delete_pks, upsert_pks, update_groups, batch_version.max(synced_version), more)
+        } else {
+            let (delete_pks, upsert_pks) = snapshot::diff_keys(
+                &mut *primary, replica_pool, table, &replica_table, &key_columns, &all_columns
+            ).await?;
+            info!("Snapshot diff for {}: {} rows to delete, {} rows to copy", table_name, delete_pks.len(), upsert_pks.len());
+            (delete_pks, upsert_pks, std::collections::HashMap::new(), current_version, false)
+        };
+        let mut upsert_pks = upsert_pks;
+        if !(delete_pks.is_empty() && upsert_pks.is_empty() && update_groups.is_empty()) {
+            disable_foreign_keys(replica_pool, redis_client, &replica_table, options, &mut fk_disabled).await?;
         }
 
-        // Fetch full rows from Primary in bulk
-        let row_query = format!("SELECT {} FROM [{}] WHERE [{}] IN ({})", select_list, table_name, pk_col, in_clause);
-        info!("Executing bulk UPSERT chunk SELECT for {} ({} items)...", table_name, chunk.len());
-        let rows = sqlx::query(&row_query).fetch_all(primary_pool).await?;
+        // The whole batch is applied in one Replica transaction, which also records the version
+        // it brings the table to, so a crash or cancellation leaves either all of it or none
+        let mut tx = replica_pool.begin().await?;
+        let suppression = triggers::suppress(&mut tx, &replica_table, trigger_mode).await?;
 
-        if rows.is_empty() {
-            continue;
+        // Perform Bulk Deletes (MERGE mode applies them as tombstones together with the upserts)
+        let delete_chunks: &[Vec<SqlValue>] = if apply_mode == ApplyMode::Merge { &[] } else { &delete_pks };
+        for chunk in delete_chunks.chunks(100) {
+            if cancel_token.is_cancelled() {
+                info!("Incremental sync cancelled for {}; aborting delete loop.", table_name);
+                break;
+            }
+            if !chunk.is_empty() {
+                let del_sql = format!("DELETE t FROM {} AS t {}", replica_table.quoted(), key_values_join(&key_columns, chunk)?);
+                info!("Executing bulk DELETE chunk for {} ({} items)...", table_name, chunk.len());
+                sqlx::query(&del_sql).execute(&mut *tx).await?;
+            }
         }
 
-        // Build INSERT query structure based on the first returned row
-        let mut cols = Vec::new();
-        let mut placeholders = Vec::new();
-        for col in rows[0].columns() {
-            cols.push(format!("[{}]", col.name()));
-            placeholders.push(format!("@p{}", cols.len()));
+        // Perform column-level Updates (UPDATE ... SET only the columns in SYS_CHANGE_COLUMNS)
+        'updates: for (mask, keys) in &update_groups {
+            let changed_cols: Vec<&ColumnSpec> = columns.iter()
+                .zip(mask.chars())
+                .filter(|(col, bit)| *bit == '1' && !pk_cols.contains(&col.name))
+                .map(|(col, _)| col)
+                .collect();
+            if changed_cols.is_empty() {
+                continue;
+            }
+
+            let changed_select = changed_cols.iter()
+                .map(|c| c.select_expr())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let set_clause = changed_cols.iter().zip(value::value_exprs(&changed_cols))
+                .map(|(c, expr)| format!("{} = {}", c.quoted(), expr))
+                .collect::<Vec<_>>()
+                .join(", ");
+            // Key parameters follow the directly bound (non-LOB) column parameters
+            let bound_params = changed_cols.iter().filter(|c| !c.lob).count();
+            let where_clause = key_columns.iter().zip(value::key_param_exprs(&key_columns, bound_params))
+                .map(|(c, p)| format!("{} = {}", c.quoted(), p))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            let update_sql = format!("UPDATE {} SET {} WHERE {}", replica_table.quoted(), set_clause, where_clause);
+
+            for chunk in keys.chunks(100) {
+                if cancel_token.is_cancelled() {
+                    info!("Incremental sync cancelled for {}; aborting update loop.", table_name);
+                    break 'updates;
+                }
+
+                let row_query = format!(
+                    "SELECT {}, {} FROM {} AS t {}",
+                    key_select_list(&key_columns), changed_select, table.quoted(), key_values_join(&key_columns, chunk)?
+                );
+                let (keys, decoded) = fetch_rows(&mut *primary, table, &key_columns, &changed_cols, sqlx::query(&row_query)).await?;
+                check_fetched(table, chunk, &keys, snapshot)?;
+
+                info!("Executing column-level UPDATE chunk for {} ({} rows, {} columns)...", table_name, decoded.len(), changed_cols.len());
+                for (key, values) in keys.into_iter().zip(decoded) {
+                    if lob::has_lob(&changed_cols) {
+                        if let Err(e) = lob::stage_values(&mut tx, &changed_cols, &values).await {
+                            log::error!("Tx Incremental LOB Staging Failed: {}", e);
+                            tx.rollback().await?;
+                            return Err(Box::new(e));
+                        }
+                    }
+
+                    let query_builder = value::bind_row(sqlx::query(&update_sql), &changed_cols, values);
+                    let query_builder = value::bind_row(query_builder, &key_columns, key.clone());
+
+                    match query_builder.execute(&mut *tx).await {
+                        // Row is missing on the replica: fall back to copying the whole row
+                        Ok(res) if res.rows_affected() == 0 => {
+                            upsert_pks.push(key);
+                        },
+                        Ok(_) => {},
+                        Err(e) => {
+                            log::error!("Tx Incremental Update Failed: {}", e);
+                            tx.rollback().await?;
+                            return Err(Box::new(e));
+                        }
+                    }
+                }
+            }
         }
 
-        let insert_sql = if has_identity {
-            format!(
-                "SET IDENTITY_INSERT [{}] ON; INSERT INTO [{}] ({}) VALUES ({});",
-                table_name, table_name, cols.join(", "), placeholders.join(", ")
-            )
-        } else {
-            format!(
-                "INSERT INTO [{}] ({}) VALUES ({});",
-                table_name, cols.join(", "), placeholders.join(", ")
-            )
-        };
+        if let Some(merge_sql) = &merge_sql {
+            // Deletes and upserts touch disjoint keys, so one pass can carry both
+            let pending: Vec<(Vec<SqlValue>, bool)> = delete_pks.into_iter().map(|k| (k, true))
+                .chain(std::mem::take(&mut upsert_pks).into_iter().map(|k| (k, false)))
+                .collect();
 
-        // Execute bulk Upsert via Transaction (DELETE then chunked INSERT)
-        let mut tx = replica_pool.begin().await?;
+            for chunk in pending.chunks(100) {
+                if cancel_token.is_cancelled() {
+                    info!("Incremental sync cancelled for {}; aborting merge loop.", table_name);
+                    break;
+                }
+                let delete_keys: Vec<Vec<SqlValue>> = chunk.iter().filter(|(_, d)| *d).map(|(k, _)| k.clone()).collect();
+                let upsert_keys: Vec<Vec<SqlValue>> = chunk.iter().filter(|(_, d)| !*d).map(|(k, _)| k.clone()).collect();
 
-        // 1. Delete existing rows in Replica to prepare for Insert
-        let del_sql = format!("DELETE FROM [{}] WHERE [{}] IN ({})", table_name, pk_col, in_clause);
-        if let Err(e) = sqlx::query(&del_sql).execute(&mut *tx).await {
-            log::error!("Tx Incremental Delete Failed: {}", e);
-            tx.rollback().await?;
-            return Err(Box::new(e));
+                let decoded = if upsert_keys.is_empty() {
+                    Vec::new()
+                } else {
+                    let row_query = format!(
+                        "SELECT {} FROM {} AS t {}",
+                        select_list, table.quoted(), key_values_join(&key_columns, &upsert_keys)?
+                    );
+                    let (keys, decoded) = fetch_rows(&mut *primary, table, &key_columns, &all_columns, sqlx::query(&row_query)).await?;
+                    check_fetched(table, &upsert_keys, &keys, snapshot)?;
+                    decoded
+                };
+
+                info!("Executing MERGE chunk for {} ({} rows, {} tombstones)...", table_name, decoded.len(), delete_keys.len());
+                // The error is not Send, so only its message is kept across the rollback
+                let merged = merge_rows(&mut tx, merge_sql, &all_columns, &key_columns, decoded, &delete_keys)
+                    .await
+                    .map_err(|e| e.to_string());
+                if let Err(e) = merged {
+                    log::error!("Tx Incremental Merge Failed: {}", e);
+                    tx.rollback().await?;
+                    return Err(e.into());
+                }
+            }
         }
 
-        // 2. Insert new rows in a tight loop over the same transaction
-        info!("Executing bulk UPSERT chunk INSERTs for {} ({} rows)...", table_name, rows.len());
-        for row in rows {
-            let mut query_builder = sqlx::query(&insert_sql);
-            for col in row.columns() {
-                let v: Option<String> = row.try_get(col.ordinal()).ok();
-                query_builder = query_builder.bind(v);
+        // Perform Bulk Upserts
+        for chunk in upsert_pks.chunks(100) {
+            if cancel_token.is_cancelled() {
+                info!("Incremental sync cancelled for {}; aborting upsert loop.", table_name);
+                break;
+            }
+            if chunk.is_empty() {
+                continue;
+            }
+            let key_join = key_values_join(&key_columns, chunk)?;
+
+            // Fetch full rows from Primary in bulk
+            let row_query = format!("SELECT {} FROM {} AS t {}", select_list, table.quoted(), key_join);
+            info!("Executing bulk UPSERT chunk SELECT for {} ({} items)...", table_name, chunk.len());
+            let (keys, decoded) = fetch_rows(&mut *primary, table, &key_columns, &all_columns, sqlx::query(&row_query)).await?;
+            check_fetched(table, chunk, &keys, snapshot)?;
+            if decoded.is_empty() {
+                continue;
+            }
+
+            // Execute bulk Upsert (DELETE then chunked INSERT)
+
+            // 1. Delete existing rows in Replica to prepare for Insert
+            let del_sql = format!("DELETE t FROM {} AS t {}", replica_table.quoted(), key_join);
+            if let Err(e) = sqlx::query(&del_sql).execute(&mut *tx).await {
+                log::error!("Tx Incremental Delete Failed: {}", e);
+                tx.rollback().await?;
+                return Err(Box::new(e));
             }
-            if let Err(e) = query_builder.execute(&mut *tx).await {
+
+            // 2. Insert new rows in a tight loop over the same transaction
+            info!("Executing bulk UPSERT chunk INSERTs for {} ({} rows)...", table_name, decoded.len());
+            if let Err(e) = insert_rows(&mut tx, &insert_sql, &all_columns, decoded).await {
                 log::error!("Tx Incremental Insert Failed: {}", e);
                 tx.rollback().await?;
                 return Err(Box::new(e));
             }
-        }
 
-        if has_identity {
-             let disable_identity = format!("SET IDENTITY_INSERT [{}] OFF;", table_name);
-             let _ = sqlx::query(&disable_identity).execute(&mut *tx).await;
+            if has_identity {
+                 let disable_identity = format!("SET IDENTITY_INSERT {} OFF;", replica_table.quoted());
+                 let _ = sqlx::query(&disable_identity).execute(&mut *tx).await;
+            }
         }
 
+        // Advance the stored version only past fully applied batches
+        if cancel_token.is_cancelled() {
+            info!("Incremental sync cancelled for {}; rolling back to version {}.", table_name, synced_version);
+            tx.rollback().await?;
+            return Ok(());
+        }
+        triggers::restore(&mut tx, &replica_table, &suppression).await?;
+        checkpoint::set_version(&mut tx, table, batch_version).await?;
         tx.commit().await?;
+        synced_version = batch_version;
+
+        // Redis only mirrors the committed version for the dashboard
+        if let Err(e) = state::set_last_version(redis_client, table_name, batch_version).await {
+            log::warn!("Failed to mirror version of {} to Redis: {}", table_name, e);
+        }
+        if !more {
+            break;
+        }
     }
 
-    // Update Redis
-    if !changes.is_empty() {
-        let last_change_ver: i64 = changes.last().unwrap().get("SYS_CHANGE_VERSION");
-        state::set_last_version(redis_client, table_name, last_change_ver).await?;
-    } else {
-        state::set_last_version(redis_client, table_name, current_version).await?;
+    if !tracked {
+        state::set_snapshot_diff_at(redis_client, table_name).await?;
+    }
+
+    if shadow_pending && !cancel_token.is_cancelled() {
+        finish_shadow_reload(replica_pool, redis_client, table).await?;
+    }
+    if !cancel_token.is_cancelled() {
+        maintain_identity(&mut *primary, replica_pool, redis_client, table, options, false).await?;
     }
 
     // Set Incremental Tracking Finished State
@@ -411,3 +1263,427 @@ async fn sync_table(
 
     Ok(())
 }
+
+/// Switches the Replica foreign keys touching `table` to NOCHECK in `nocheck` mode, once per pass
+/// and only before it writes anything, so passes without changes leave the keys trusted and give
+/// `foreign_keys::revalidate` nothing to check.
+async fn disable_foreign_keys(
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+    options: &SyncOptions,
+    disabled: &mut bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if options.foreign_key_mode == ForeignKeyMode::NoCheck && !*disabled {
+        foreign_keys::disable(replica_pool, redis_client, std::slice::from_ref(table)).await?;
+        *disabled = true;
+    }
+    Ok(())
+}
+
+/// The columns of `table` on the Primary, in ordinal order.
+pub(crate) async fn load_columns(
+    primary: &mut MssqlConnection,
+    table: &TableRef,
+) -> Result<Vec<ColumnSpec>, Box<dyn std::error::Error>> {
+    let cols_query = format!(
+        "SELECT COLUMN_NAME, DATA_TYPE, CHARACTER_MAXIMUM_LENGTH FROM INFORMATION_SCHEMA.COLUMNS WHERE TABLE_SCHEMA = '{}' AND TABLE_NAME = '{}' ORDER BY ORDINAL_POSITION",
+        table.schema_literal(), table.name_literal()
+    );
+    let column_rows = sqlx::query(&cols_query).fetch_all(&mut *primary).await?;
+    Ok(column_rows.iter()
+        .map(|row| ColumnSpec::new(
+            &row.get::<String, _>("COLUMN_NAME"),
+            &row.get::<String, _>("DATA_TYPE"),
+            row.try_get::<Option<i32>, _>("CHARACTER_MAXIMUM_LENGTH").ok().flatten(),
+        ))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Swaps a caught-up shadow table in for the live one and clears the reload flags.
+async fn finish_shadow_reload(
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+    schema::swap_shadow_table(replica_pool, table).await?;
+    state::clear_shadow_reload_pending(redis_client, table_name).await?;
+    state::clear_force_full_load(redis_client, table_name).await?;
+    state::clear_needs_reload(redis_client, table_name).await?;
+    // The swapped-in table was loaded with IDENTITY_INSERT
+    state::request_identity_reseed(redis_client, table_name).await?;
+    info!("Shadow reload complete for table: {}", table_name);
+    Ok(())
+}
+
+/// Aligns the Replica's identity with the Primary's after a full load (`force`), when requested
+/// through `mssql_sync:reseed_identity:<table>`, or every `identity_reseed_interval_secs`.
+async fn maintain_identity(
+    primary: &mut MssqlConnection,
+    replica_pool: &Pool<Mssql>,
+    redis_client: &Client,
+    table: &TableRef,
+    options: &SyncOptions,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let table_name = &table.to_string();
+    let requested = state::take_identity_reseed(redis_client, table_name).await?;
+    let interval = options.identity_reseed_interval_secs;
+    let due = interval > 0 && state::is_identity_reseed_due(redis_client, table_name, interval).await?;
+    if !(force || requested || due) {
+        return Ok(());
+    }
+
+    if let Some(value) = schema::reseed_identity(primary, replica_pool, table).await? {
+        info!("Reseeded identity of {} to {}", table_name, value);
+    }
+    state::set_identity_reseed_at(redis_client, table_name).await?;
+    Ok(())
+}
+
+/// Builds a `JOIN (VALUES ...)` clause that matches rows of the table aliased `t` on every key
+/// column. Keys are ordered like `key_columns` and rendered as literals typed to their column.
+pub(crate) fn key_values_join(key_columns: &[&ColumnSpec], keys: &[Vec<SqlValue>]) -> Result<String, String> {
+    let on_clause = key_columns.iter().enumerate()
+        .map(|(i, c)| format!("t.{} = k.[__ct_k{}]", c.quoted(), i))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    Ok(format!("JOIN (VALUES {}) AS k ({}) ON {}", key_values(key_columns, keys)?, key_aliases(key_columns.len()), on_clause))
+}
+
+/// `(<literal>, <literal>), ...` row constructors for key tuples.
+fn key_values(key_columns: &[&ColumnSpec], keys: &[Vec<SqlValue>]) -> Result<String, String> {
+    let rows = keys.iter()
+        .map(|key| {
+            let literals = key_columns.iter().zip(key)
+                .map(|(c, v)| value::key_literal(c, v))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("({})", literals.join(", ")))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(rows.join(", "))
+}
+
+/// `[__ct_k0], [__ct_k1], ...` column aliases for the derived table built by `key_values`.
+fn key_aliases(count: usize) -> String {
+    (0..count).map(|i| format!("[__ct_k{}]", i)).collect::<Vec<_>>().join(", ")
+}
+
+/// Row-value comparison `(k0, k1, ...) > (@p1, @p2, ...)` over the table aliased `t`, expanded
+/// as `k0 > @p1 OR (k0 = @p1 AND k1 > @p2) ...`. Parameters are numbered after `first_param`
+/// like `value::value_exprs_from`, so the previous page's last key is bound with `value::bind_row`.
+fn keyset_predicate(key_columns: &[&ColumnSpec], first_param: usize) -> String {
+    // Parameters are typed to their column, so char/varchar keys compare under the column's
+    // collation and order like the ORDER BY; an nvarchar parameter would promote the column
+    let params = value::key_param_exprs(key_columns, first_param);
+    (0..key_columns.len())
+        .map(|i| {
+            let mut terms: Vec<String> = (0..i)
+                .map(|j| format!("t.{} = {}", key_columns[j].quoted(), params[j]))
+                .collect();
+            terms.push(format!("t.{} > {}", key_columns[i].quoted(), params[i]));
+            format!("({})", terms.join(" AND "))
+        })
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// Every key column selected losslessly as `[__ct_pk_N]`, read back by `decode_key`.
+pub(crate) fn key_select_list(key_columns: &[&ColumnSpec]) -> String {
+    key_columns.iter().enumerate()
+        .map(|(i, c)| c.select_expr_as(&format!("__ct_pk_{}", i)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Decodes the key tuple selected with `key_select_list`.
+pub(crate) fn decode_key(row: &MssqlRow, key_columns: &[&ColumnSpec]) -> Result<Vec<SqlValue>, String> {
+    key_columns.iter().enumerate()
+        .map(|(i, c)| value::decode_as(row, c, &format!("__ct_pk_{}", i)))
+        .collect()
+}
+
+/// Runs a row SELECT built from `key_select_list` (when the table has a key) and the columns'
+/// `select_expr`s, decodes every value and fetches LOB content. Returns each row's key tuple
+/// alongside its values.
+pub(crate) async fn fetch_rows(
+    primary: &mut MssqlConnection,
+    table: &TableRef,
+    key_columns: &[&ColumnSpec],
+    columns: &[&ColumnSpec],
+    query: Query<'_, Mssql, MssqlArguments>,
+) -> Result<(Vec<Vec<SqlValue>>, Vec<Vec<SqlValue>>), Box<dyn std::error::Error>> {
+    let rows = query.fetch_all(&mut *primary).await?;
+
+    let mut keys = Vec::with_capacity(rows.len());
+    let mut decoded = Vec::with_capacity(rows.len());
+    for row in &rows {
+        keys.push(decode_key(row, key_columns)?);
+        decoded.push(value::decode_row(row, columns)?);
+    }
+
+    if lob::has_lob(columns) {
+        lob::fetch_values(primary, table, key_columns, columns, &keys, &mut decoded).await?;
+    }
+
+    Ok((keys, decoded))
+}
+
+/// Checks that the row of every changed key in `requested` was read back from the Primary. Inside
+/// a snapshot each of them exists, so a missing row means its key did not match and the change
+/// would be lost. Without a snapshot the row may have been deleted since the changes were read;
+/// that delete is applied by the next pass.
+fn check_fetched(
+    table: &TableRef,
+    requested: &[Vec<SqlValue>],
+    fetched: &[Vec<SqlValue>],
+    snapshot: bool,
+) -> Result<(), String> {
+    let missing: Vec<&Vec<SqlValue>> = requested.iter().filter(|k| !fetched.contains(k)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if !snapshot {
+        log::warn!("{} changed rows of {} were deleted on the Primary before they could be read: {:?}", missing.len(), table, missing);
+        return Ok(());
+    }
+    Err(format!(
+        "{} changed rows of {} could not be read back from the Primary by their key: {:?}",
+        missing.len(), table, missing
+    ))
+}
+
+/// Inserts decoded rows with `insert_sql` on the replica transaction, staging LOB values first.
+pub(crate) async fn insert_rows(
+    tx: &mut MssqlConnection,
+    insert_sql: &str,
+    columns: &[&ColumnSpec],
+    rows: Vec<Vec<SqlValue>>,
+) -> Result<(), sqlx::Error> {
+    let has_lob = lob::has_lob(columns);
+    for values in rows {
+        if has_lob {
+            lob::stage_values(&mut *tx, columns, &values).await?;
+        }
+        value::bind_row(sqlx::query(insert_sql), columns, values)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// INSERT statement for every column of the table, wrapped in IDENTITY_INSERT when needed.
+pub(crate) fn build_insert_sql(table: &TableRef, columns: &[&ColumnSpec], has_identity: bool) -> String {
+    let cols = columns.iter().map(|c| c.quoted()).collect::<Vec<_>>();
+    let placeholders = value::value_exprs(columns);
+
+    if has_identity {
+        format!(
+            "SET IDENTITY_INSERT {} ON; INSERT INTO {} ({}) VALUES ({});",
+            table.quoted(), table.quoted(), cols.join(", "), placeholders.join(", ")
+        )
+    } else {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({});",
+            table.quoted(), cols.join(", "), placeholders.join(", ")
+        )
+    }
+}
+
+/// Statements of the MERGE apply mode for one table.
+struct MergeSql {
+    /// Recreates the `#ct_merge` staging table (batch, so it lives for the session)
+    create_staging: String,
+    /// Stages one changed row, parameterised like `build_insert_sql`
+    stage_row: String,
+    /// Applies every staged row and tombstone to the table
+    merge: String,
+}
+
+impl MergeSql {
+    fn new(
+        table: &TableRef,
+        columns: &[&ColumnSpec],
+        pk_cols: &[String],
+        identity_cols: &[String],
+        has_identity: bool,
+    ) -> Self {
+        let cols = columns.iter().map(|c| c.quoted()).collect::<Vec<_>>();
+
+        // The outer join makes every staged column nullable (tombstones only carry the key) and
+        // keeps SELECT ... INTO from copying the IDENTITY property
+        let create_staging = format!(
+            "IF OBJECT_ID('tempdb..#ct_merge') IS NOT NULL DROP TABLE #ct_merge;
+             SELECT TOP 0 CAST('U' AS CHAR(1)) AS [__ct_op], {}
+             INTO #ct_merge FROM (SELECT 1 AS [__ct_one]) AS d LEFT JOIN {} AS t ON 1 = 0;",
+            columns.iter().map(|c| format!("t.{}", c.quoted())).collect::<Vec<_>>().join(", "),
+            table.quoted()
+        );
+
+        let stage_row = format!(
+            "INSERT INTO #ct_merge ([__ct_op], {}) VALUES ('U', {});",
+            cols.join(", "), value::value_exprs(columns).join(", ")
+        );
+
+        let on_clause = pk_cols.iter()
+            .map(|c| format!("t.[{0}] = s.[{0}]", c.replace(']', "]]")))
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        // Key and identity columns cannot change through an update
+        let set_clause = columns.iter()
+            .filter(|c| !pk_cols.contains(&c.name) && !identity_cols.contains(&c.name))
+            .map(|c| format!("t.{0} = s.{0}", c.quoted()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let when_matched_update = if set_clause.is_empty() {
+            String::new()
+        } else {
+            format!("WHEN MATCHED THEN UPDATE SET {} ", set_clause)
+        };
+        let merge = format!(
+            "MERGE {} AS t USING #ct_merge AS s ON {} \
+             WHEN MATCHED AND s.[__ct_op] = 'D' THEN DELETE \
+             {}WHEN NOT MATCHED BY TARGET AND s.[__ct_op] = 'U' THEN INSERT ({}) VALUES ({});",
+            table.quoted(), on_clause, when_matched_update, cols.join(", "),
+            columns.iter().map(|c| format!("s.{}", c.quoted())).collect::<Vec<_>>().join(", ")
+        );
+        let merge = if has_identity {
+            format!("SET IDENTITY_INSERT {} ON; {} SET IDENTITY_INSERT {} OFF;", table.quoted(), merge, table.quoted())
+        } else {
+            merge
+        };
+
+        MergeSql { create_staging, stage_row, merge }
+    }
+}
+
+/// Stages decoded rows and the keys of deleted rows in `#ct_merge` on the replica transaction,
+/// then applies them with a single MERGE.
+async fn merge_rows(
+    tx: &mut MssqlConnection,
+    merge_sql: &MergeSql,
+    columns: &[&ColumnSpec],
+    key_columns: &[&ColumnSpec],
+    rows: Vec<Vec<SqlValue>>,
+    delete_keys: &[Vec<SqlValue>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    sqlx::query(&merge_sql.create_staging).execute(&mut *tx).await?;
+    insert_rows(&mut *tx, &merge_sql.stage_row, columns, rows).await?;
+
+    if !delete_keys.is_empty() {
+        let tombstone_sql = format!(
+            "INSERT INTO #ct_merge ([__ct_op], {}) SELECT 'D', {} FROM (VALUES {}) AS k ({});",
+            key_columns.iter().map(|c| c.quoted()).collect::<Vec<_>>().join(", "),
+            (0..key_columns.len()).map(|i| format!("k.[__ct_k{}]", i)).collect::<Vec<_>>().join(", "),
+            key_values(key_columns, delete_keys)?,
+            key_aliases(key_columns.len())
+        );
+        sqlx::query(&tombstone_sql).execute(&mut *tx).await?;
+    }
+
+    sqlx::query(&merge_sql.merge).execute(&mut *tx).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datetime_keys_keep_their_fractional_seconds() {
+        let id = ColumnSpec::new("Id", "int", None).unwrap();
+        let at = ColumnSpec::new("At", "datetime", None).unwrap();
+        let key_columns = [&id, &at];
+        let keys = vec![vec![SqlValue::I32(7), SqlValue::Temporal("2024-03-01T10:15:42.123".to_string())]];
+
+        assert_eq!(key_select_list(&key_columns), "[Id] AS [__ct_pk_0], CONVERT(VARCHAR(100), [At], 126) AS [__ct_pk_1]");
+        assert_eq!(
+            key_values_join(&key_columns, &keys).unwrap(),
+            "JOIN (VALUES (7, CONVERT(DATETIME, N'2024-03-01T10:15:42.123', 126))) AS k ([__ct_k0], [__ct_k1]) \
+             ON t.[Id] = k.[__ct_k0] AND t.[At] = k.[__ct_k1]"
+        );
+    }
+
+    #[test]
+    fn varbinary_keys_convert_back_from_hex() {
+        let hash = ColumnSpec::new("Hash", "varbinary", Some(32)).unwrap();
+        let keys = vec![
+            vec![SqlValue::Binary(vec![0x00, 0xAB, 0x10])],
+            vec![SqlValue::Binary(Vec::new())],
+        ];
+
+        assert_eq!(
+            key_select_list(&[&hash]),
+            "CONVERT(VARCHAR(4000), CAST([Hash] AS VARBINARY(2000)), 2) AS [__ct_pk_0]"
+        );
+        assert_eq!(
+            key_values_join(&[&hash], &keys).unwrap(),
+            "JOIN (VALUES (CONVERT(VARBINARY(8000), N'00AB10', 2)), (CONVERT(VARBINARY(8000), N'', 2))) \
+             AS k ([__ct_k0]) ON t.[Hash] = k.[__ct_k0]"
+        );
+    }
+
+    #[test]
+    fn key_literals_are_typed_to_their_column() {
+        let name = ColumnSpec::new("Name", "varchar", Some(50)).unwrap();
+        let price = ColumnSpec::new("Price", "money", None).unwrap();
+        let big = ColumnSpec::new("Big", "bigint", None).unwrap();
+        let ratio = ColumnSpec::new("Ratio", "float", None).unwrap();
+        let key = vec![
+            SqlValue::Text("O'Brien".to_string()),
+            SqlValue::Decimal("12.3456".to_string()),
+            SqlValue::I64(3_000_000_000),
+            SqlValue::F64(0.1),
+        ];
+
+        assert_eq!(
+            key_values(&[&name, &price, &big, &ratio], &[key]).unwrap(),
+            "(CAST(N'O''Brien' AS VARCHAR(8000)), CAST(N'12.3456' AS MONEY), CAST(3000000000 AS BIGINT), CAST(1e-1 AS FLOAT))"
+        );
+    }
+
+    #[test]
+    fn changed_keys_missing_from_a_snapshot_read_are_an_error() {
+        let table = TableRef::new("dbo", "Orders");
+        let requested = vec![vec![SqlValue::I32(1)], vec![SqlValue::I32(2)]];
+        let fetched = vec![vec![SqlValue::I32(2)]];
+
+        assert!(check_fetched(&table, &requested, &requested, true).is_ok());
+        assert!(check_fetched(&table, &requested, &fetched, true).is_err());
+        assert!(check_fetched(&table, &requested, &fetched, false).is_ok());
+    }
+
+    #[test]
+    fn keyset_predicate_expands_composite_keys() {
+        let region = ColumnSpec::new("Region", "varchar", Some(10)).unwrap();
+        let at = ColumnSpec::new("At", "datetime2", None).unwrap();
+        let id = ColumnSpec::new("Id", "bigint", None).unwrap();
+
+        assert_eq!(
+            keyset_predicate(&[&region, &at, &id], 3),
+            "(t.[Region] > CAST(@p4 AS VARCHAR(8000))) \
+             OR (t.[Region] = CAST(@p4 AS VARCHAR(8000)) AND t.[At] > CONVERT(DATETIME2, @p5, 126)) \
+             OR (t.[Region] = CAST(@p4 AS VARCHAR(8000)) AND t.[At] = CONVERT(DATETIME2, @p5, 126) AND t.[Id] > @p6)"
+        );
+    }
+
+    #[test]
+    fn keyset_predicate_quotes_closing_brackets() {
+        let odd = ColumnSpec::new("Odd]Name", "int", None).unwrap();
+        let hash = ColumnSpec::new("Hash", "binary", Some(8)).unwrap();
+
+        assert_eq!(
+            keyset_predicate(&[&odd, &hash], 0),
+            "(t.[Odd]]Name] > @p1) OR (t.[Odd]]Name] = @p1 AND t.[Hash] > CONVERT(VARBINARY(8000), @p2, 2))"
+        );
+        assert_eq!(key_select_list(&[&odd]), "[Odd]]Name] AS [__ct_pk_0]");
+    }
+
+    #[test]
+    fn null_keys_are_rejected() {
+        let id = ColumnSpec::new("Id", "int", None).unwrap();
+        assert!(key_values_join(&[&id], &[vec![SqlValue::Null]]).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Appended to the name of a shadow table and to the constraints created on it, since
+/// constraint names are unique per schema.
+pub const SHADOW_SUFFIX: &str = "__ct_reload";
+
+/// A replicated table, identified by its schema and name.
+///
+/// `Display` renders the bracketed `[schema].[name]` form (see `quoted()`) used in Redis keys,
+/// the Replica's state table, logs and generated T-SQL. Unlike `schema.name`, it stays
+/// unambiguous when a schema or table name contains a dot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct TableRef {
+    pub schema: String,
+    pub name: String,
+}
+
+impl TableRef {
+    pub fn new(schema: &str, name: &str) -> Self {
+        TableRef {
+            schema: schema.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// `[schema].[name]`, escaped for use as a T-SQL identifier.
+    pub fn quoted(&self) -> String {
+        format!("[{}].[{}]", self.schema.replace(']', "]]"), self.name.replace(']', "]]"))
+    }
+
+    /// `OBJECT_ID(N'[schema].[name]')`, escaped for use inside a T-SQL string literal.
+    pub fn object_id(&self) -> String {
+        format!("OBJECT_ID(N'{}')", self.quoted().replace('\'', "''"))
+    }
+
+    /// The copy a shadow reload builds and swaps in (`[schema].[name__ct_reload]`).
+    pub fn shadow(&self) -> TableRef {
+        TableRef::new(&self.schema, &format!("{}{}", self.name, SHADOW_SUFFIX))
+    }
+
+    /// The `schema.name` form tables were keyed by before, kept to migrate state written in it.
+    pub fn dotted(&self) -> String {
+        format!("{}.{}", self.schema, self.name)
+    }
+
+    /// Schema name escaped for comparison against a catalog column in a string literal.
+    pub fn schema_literal(&self) -> String {
+        self.schema.replace('\'', "''")
+    }
+
+    /// Table name escaped for comparison against a catalog column in a string literal.
+    pub fn name_literal(&self) -> String {
+        self.name.replace('\'', "''")
+    }
+}
+
+impl fmt::Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.quoted())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_keeps_dotted_names_apart() {
+        let dotted_schema = TableRef::new("a.b", "c");
+        let dotted_name = TableRef::new("a", "b.c");
+        assert_eq!(dotted_schema.to_string(), "[a.b].[c]");
+        assert_eq!(dotted_name.to_string(), "[a].[b.c]");
+        assert_eq!(dotted_schema.dotted(), dotted_name.dotted());
+    }
+
+    #[test]
+    fn display_escapes_closing_brackets() {
+        assert_eq!(TableRef::new("dbo", "a].[b").to_string(), "[dbo].[a]].[b]");
+    }
+}
@@ -0,0 +1,97 @@
+use sqlx::mssql::MssqlConnection;
+use crate::table::TableRef;
+
+// Triggers that exist only on the Replica would otherwise fire for every row the sync writes.
+// By default each apply transaction sets a session context flag that such triggers check to skip
+// replicated writes. Disabling the triggers instead needs no change to them, but DISABLE TRIGGER
+// takes a schema modification lock on the table that blocks every reader until the batch
+// commits, so that mode is opt-in.
+
+/// SESSION_CONTEXT key set to 1 while the sync applies changes on a connection.
+pub const SESSION_FLAG: &str = "ct_sync";
+
+/// Whether Replica triggers fire for the rows the sync writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Triggers fire, but can skip replicated writes by checking `SESSION_CONTEXT(N'ct_sync')`
+    Guard,
+    /// Enabled triggers are disabled for the duration of each apply transaction
+    Disable,
+    /// Triggers fire as for any other write, without the session flag
+    Allow,
+}
+
+impl TriggerMode {
+    /// `suppress` is the earlier name of `disable`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "guard" => Some(TriggerMode::Guard),
+            "disable" | "suppress" => Some(TriggerMode::Disable),
+            "allow" => Some(TriggerMode::Allow),
+            _ => None,
+        }
+    }
+}
+
+/// What `suppress` changed on a transaction connection, undone by `restore`.
+#[derive(Debug)]
+pub enum Suppression {
+    None,
+    /// The session flag is set
+    Guard,
+    /// These triggers were disabled
+    Disabled(Vec<String>),
+}
+
+/// Keeps the triggers of `table` from acting on the sync's writes on the given transaction
+/// connection, as chosen by `mode`.
+pub async fn suppress(conn: &mut MssqlConnection, table: &TableRef, mode: TriggerMode) -> Result<Suppression, sqlx::Error> {
+    match mode {
+        TriggerMode::Allow => Ok(Suppression::None),
+        TriggerMode::Guard => {
+            sqlx::query(&session_flag_statement("1")).execute(&mut *conn).await?;
+            Ok(Suppression::Guard)
+        },
+        TriggerMode::Disable => {
+            // Triggers disabled by someone else stay disabled afterwards
+            let trigger_query = format!(
+                "SELECT name FROM sys.triggers WHERE parent_id = {} AND is_disabled = 0",
+                table.object_id()
+            );
+            let names: Vec<String> = sqlx::query_scalar(&trigger_query).fetch_all(&mut *conn).await?;
+            if !names.is_empty() {
+                sqlx::query(&trigger_statement("DISABLE", table, &names)).execute(&mut *conn).await?;
+            }
+            Ok(Suppression::Disabled(names))
+        },
+    }
+}
+
+/// Undoes `suppress` before the transaction commits.
+pub async fn restore(conn: &mut MssqlConnection, table: &TableRef, suppression: &Suppression) -> Result<(), sqlx::Error> {
+    match suppression {
+        Suppression::None => {},
+        Suppression::Guard => {
+            sqlx::query(&session_flag_statement("NULL")).execute(&mut *conn).await?;
+        },
+        Suppression::Disabled(names) if !names.is_empty() => {
+            sqlx::query(&trigger_statement("ENABLE", table, names)).execute(&mut *conn).await?;
+        },
+        Suppression::Disabled(_) => {},
+    }
+    Ok(())
+}
+
+/// Session context is not transactional, so a rolled-back batch leaves the flag set on its
+/// connection until the next `suppress`; the pool only serves the sync itself.
+fn session_flag_statement(value: &str) -> String {
+    format!("EXEC sp_set_session_context N'{}', {};", SESSION_FLAG, value)
+}
+
+fn trigger_statement(action: &str, table: &TableRef, names: &[String]) -> String {
+    let triggers = names.iter()
+        .map(|n| format!("[{}]", n.replace(']', "]]")))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} TRIGGER {} ON {}", action, triggers, table.quoted())
+}
@@ -0,0 +1,417 @@
+use sqlx::{Mssql, Row};
+use sqlx::mssql::{MssqlArguments, MssqlRow};
+use sqlx::query::Query;
+
+/// How values of a SQL Server column type travel between Primary and Replica.
+///
+/// sqlx only decodes integers, floats, bit and character data natively for MSSQL, so every
+/// other type is selected in an exact textual form (decimal digits, ISO 8601, hex) and converted
+/// back by SQL Server on the replica side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Bit,
+    TinyInt,
+    SmallInt,
+    Int,
+    BigInt,
+    Real,
+    Float,
+    /// decimal, numeric, money, smallmoney: exact digits as text
+    Decimal,
+    /// char, varchar, nchar, nvarchar, text, ntext, xml
+    Text,
+    /// date, time, datetime, datetime2, smalldatetime, datetimeoffset: ISO 8601 text
+    Temporal,
+    Guid,
+    /// binary, varbinary, image, rowversion and CLR types (hierarchyid, geometry, geography): hex text
+    Binary,
+}
+
+impl ValueKind {
+    pub fn from_data_type(data_type: &str) -> Result<Self, String> {
+        let kind = match data_type.to_lowercase().as_str() {
+            "bit" => ValueKind::Bit,
+            "tinyint" => ValueKind::TinyInt,
+            "smallint" => ValueKind::SmallInt,
+            "int" => ValueKind::Int,
+            "bigint" => ValueKind::BigInt,
+            "real" => ValueKind::Real,
+            "float" => ValueKind::Float,
+            "decimal" | "numeric" | "money" | "smallmoney" => ValueKind::Decimal,
+            "char" | "varchar" | "nchar" | "nvarchar" | "text" | "ntext" | "xml" | "sysname" => ValueKind::Text,
+            "date" | "time" | "datetime" | "datetime2" | "smalldatetime" | "datetimeoffset" => ValueKind::Temporal,
+            "uniqueidentifier" => ValueKind::Guid,
+            "binary" | "varbinary" | "image" | "timestamp" | "rowversion"
+            | "hierarchyid" | "geometry" | "geography" => ValueKind::Binary,
+            other => return Err(format!("Unsupported data type for replication: {}", other)),
+        };
+        Ok(kind)
+    }
+}
+
+/// A column of a replicated table together with its transport kind.
+#[derive(Debug, Clone)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub data_type: String,
+    pub kind: ValueKind,
+    /// Values may not fit a single sqlx parameter or non-PLP result column (MAX types,
+    /// text/ntext/image/xml, wide char/binary), so they are moved in pieces (see `lob`).
+    pub lob: bool,
+}
+
+impl ColumnSpec {
+    /// `max_length` is INFORMATION_SCHEMA's CHARACTER_MAXIMUM_LENGTH (-1 for MAX types).
+    pub fn new(name: &str, data_type: &str, max_length: Option<i32>) -> Result<Self, String> {
+        let kind = ValueKind::from_data_type(data_type)
+            .map_err(|e| format!("Column [{}]: {}", name, e))?;
+        let data_type = data_type.to_lowercase();
+        let lob = match kind {
+            _ if ["text", "ntext", "image", "xml"].contains(&data_type.as_str()) => true,
+            _ if max_length == Some(-1) => true,
+            ValueKind::Text => max_length.is_some_and(|len| len > crate::lob::TEXT_PIECE_CHARS as i32),
+            ValueKind::Binary => max_length.is_some_and(|len| len > crate::lob::BINARY_PIECE_BYTES as i32),
+            _ => false,
+        };
+        Ok(ColumnSpec {
+            name: name.to_string(),
+            data_type,
+            kind,
+            lob,
+        })
+    }
+
+    /// The column name as a bracketed T-SQL identifier.
+    pub fn quoted(&self) -> String {
+        format!("[{}]", self.name.replace(']', "]]"))
+    }
+
+    /// SELECT expression that returns the column in a form `decode` can read losslessly.
+    /// LOB columns only return their length here; their content is fetched by `lob`.
+    pub fn select_expr(&self) -> String {
+        self.select_expr_as(&self.name)
+    }
+
+    /// `select_expr` returning the column under `alias`, which `decode_as` reads it back from.
+    pub fn select_expr_as(&self, alias: &str) -> String {
+        let name = &self.quoted();
+        let alias = &format!("[{}]", alias.replace(']', "]]"));
+        if self.lob {
+            return format!("CAST({} AS BIGINT) AS {}", crate::lob::length_expr(self, name), alias);
+        }
+        match (self.kind, self.data_type.as_str()) {
+            // money styles: 2 keeps all four decimal places without thousands separators
+            (ValueKind::Decimal, "money" | "smallmoney") => format!("CONVERT(VARCHAR(100), {}, 2) AS {}", name, alias),
+            (ValueKind::Decimal, _) => format!("CONVERT(VARCHAR(100), {}) AS {}", name, alias),
+            // Style 126 (ISO 8601) keeps every fractional digit and the offset of datetimeoffset
+            (ValueKind::Temporal, _) => format!("CONVERT(VARCHAR(100), {}, 126) AS {}", name, alias),
+            (ValueKind::Guid, _) => format!("CONVERT(CHAR(36), {}) AS {}", name, alias),
+            // Style 2: hex digits without the 0x prefix (via varbinary so CLR types are not stringified)
+            (ValueKind::Binary, _) => format!("CONVERT(VARCHAR(4000), CAST({} AS VARBINARY(2000)), 2) AS {}", name, alias),
+            _ if alias == name => name.to_string(),
+            _ => format!("{} AS {}", name, alias),
+        }
+    }
+
+    /// Converts `expr`, a parameter or string literal holding a key value in the text form
+    /// `select_expr` reads it in, to the column's type. Every comparison with the key column
+    /// then converts the value side, so it stays exact and can seek the column's index.
+    fn typed_key_expr(&self, expr: &str) -> String {
+        match (self.kind, self.data_type.as_str()) {
+            // A varchar value takes the column's collation; nvarchar would convert the column
+            (ValueKind::Text, "char" | "varchar") => format!("CAST({} AS VARCHAR(8000))", expr),
+            (ValueKind::Decimal, "money" | "smallmoney") => format!("CAST({} AS {})", expr, self.data_type.to_uppercase()),
+            (ValueKind::Temporal, _) => format!("CONVERT({}, {}, 126)", self.data_type.to_uppercase(), expr),
+            (ValueKind::Guid, _) => format!("CAST({} AS UNIQUEIDENTIFIER)", expr),
+            (ValueKind::Binary, "binary" | "varbinary" | "timestamp" | "rowversion") => format!("CONVERT(VARBINARY(8000), {}, 2)", expr),
+            (ValueKind::Binary, _) => format!("CAST(CONVERT(VARBINARY(8000), {}, 2) AS {})", expr, self.data_type.to_uppercase()),
+            _ => expr.to_string(),
+        }
+    }
+}
+
+/// Parameters `@pN` (numbered after `first_param`) for the values of `key_columns`, converted to
+/// each column's type. Values are bound with `bind_row`.
+pub fn key_param_exprs(key_columns: &[&ColumnSpec], first_param: usize) -> Vec<String> {
+    key_columns.iter().enumerate()
+        .map(|(i, c)| c.typed_key_expr(&format!("@p{}", first_param + i + 1)))
+        .collect()
+}
+
+/// Literal of a key value, typed to its column like `key_param_exprs`. Key values are never
+/// NULL or LOBs, which could not match a row.
+pub fn key_literal(column: &ColumnSpec, value: &SqlValue) -> Result<String, String> {
+    let literal = match value {
+        SqlValue::Bool(v) => format!("CAST({} AS BIT)", *v as u8),
+        SqlValue::U8(v) => format!("CAST({} AS TINYINT)", v),
+        SqlValue::I16(v) => format!("CAST({} AS SMALLINT)", v),
+        SqlValue::I32(v) => v.to_string(),
+        // Larger literals would be typed numeric
+        SqlValue::I64(v) => format!("CAST({} AS BIGINT)", v),
+        // Exponent notation makes a float literal with the shortest digits that round-trip
+        SqlValue::F32(v) => format!("CAST({:e} AS REAL)", v),
+        SqlValue::F64(v) => format!("CAST({:e} AS FLOAT)", v),
+        SqlValue::Null | SqlValue::Lob(_) => {
+            return Err(format!("Key column [{}] ({}) has no usable value: {:?}", column.name, column.data_type, value));
+        }
+        _ => {
+            let text = key_to_text(value).unwrap_or_default();
+            column.typed_key_expr(&format!("N'{}'", text.replace('\'', "''")))
+        }
+    };
+    Ok(literal)
+}
+
+/// Value expressions for an INSERT/UPDATE of `columns` on the replica. Directly bound columns
+/// get `@pN` parameters numbered from 1; LOB columns read the value staged by `lob::stage_values`
+/// under their position in `columns`.
+pub fn value_exprs(columns: &[&ColumnSpec]) -> Vec<String> {
+    value_exprs_from(columns, 0)
+}
+
+/// `value_exprs` with parameters numbered after `first_param`, for statements that bind
+/// several rows.
+pub fn value_exprs_from(columns: &[&ColumnSpec], first_param: usize) -> Vec<String> {
+    let mut param = first_param;
+    columns.iter().enumerate().map(|(i, c)| {
+        if c.lob {
+            return crate::lob::staged_value_expr(c, i);
+        }
+        param += 1;
+        match c.kind {
+            ValueKind::Binary => format!("CONVERT(VARBINARY(MAX), @p{}, 2)", param),
+            _ => format!("@p{}", param),
+        }
+    }).collect()
+}
+
+/// A single column value read from the Primary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    U8(u8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Decimal(String),
+    Text(String),
+    Temporal(String),
+    Guid(uuid::Uuid),
+    Binary(Vec<u8>),
+    Lob(LobValue),
+}
+
+/// A large value moved in pieces: text pieces are at most `lob::TEXT_PIECE_CHARS` UTF-16 code
+/// units, binary pieces are hex strings of at most `lob::BINARY_PIECE_BYTES` bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobValue {
+    pub binary: bool,
+    /// Length reported by the Primary, in characters (text) or bytes (binary)
+    pub length: i64,
+    pub pieces: Vec<String>,
+}
+
+impl LobValue {
+    /// Length of the pieces fetched so far, in the same unit as `length`.
+    pub fn fetched_length(&self) -> i64 {
+        self.pieces.iter().map(|p| {
+            if self.binary { (p.len() / 2) as i64 } else { p.encode_utf16().count() as i64 }
+        }).sum()
+    }
+}
+
+/// Decodes `column` from a row selected with `ColumnSpec::select_expr`. A value that cannot be
+/// decoded is an error; only a SQL NULL becomes `SqlValue::Null`.
+pub fn decode(row: &MssqlRow, column: &ColumnSpec) -> Result<SqlValue, String> {
+    decode_as(row, column, &column.name)
+}
+
+/// `decode` for a column selected with `ColumnSpec::select_expr_as` under `alias`.
+pub fn decode_as(row: &MssqlRow, column: &ColumnSpec, alias: &str) -> Result<SqlValue, String> {
+    let name = alias;
+    let err = |e: sqlx::Error| format!("Failed to decode column [{}] ({}): {}", column.name, column.data_type, e);
+
+    if column.lob {
+        let length = row.try_get::<Option<i64>, _>(name).map_err(err)?;
+        return Ok(length.map_or(SqlValue::Null, |length| SqlValue::Lob(LobValue {
+            binary: column.kind == ValueKind::Binary,
+            length,
+            pieces: Vec::new(),
+        })));
+    }
+
+    let value = match column.kind {
+        ValueKind::Bit => row.try_get::<Option<bool>, _>(name).map_err(err)?.map(SqlValue::Bool),
+        ValueKind::TinyInt => row.try_get::<Option<u8>, _>(name).map_err(err)?.map(SqlValue::U8),
+        ValueKind::SmallInt => row.try_get::<Option<i16>, _>(name).map_err(err)?.map(SqlValue::I16),
+        ValueKind::Int => row.try_get::<Option<i32>, _>(name).map_err(err)?.map(SqlValue::I32),
+        ValueKind::BigInt => row.try_get::<Option<i64>, _>(name).map_err(err)?.map(SqlValue::I64),
+        ValueKind::Real => row.try_get::<Option<f32>, _>(name).map_err(err)?.map(SqlValue::F32),
+        ValueKind::Float => row.try_get::<Option<f64>, _>(name).map_err(err)?.map(SqlValue::F64),
+        ValueKind::Decimal => row.try_get::<Option<String>, _>(name).map_err(err)?.map(SqlValue::Decimal),
+        ValueKind::Text => row.try_get::<Option<String>, _>(name).map_err(err)?.map(SqlValue::Text),
+        ValueKind::Temporal => row.try_get::<Option<String>, _>(name).map_err(err)?.map(SqlValue::Temporal),
+        ValueKind::Guid => match row.try_get::<Option<String>, _>(name).map_err(err)? {
+            Some(s) => Some(SqlValue::Guid(
+                uuid::Uuid::parse_str(s.trim())
+                    .map_err(|e| format!("Failed to decode column [{}] (uniqueidentifier): {}", column.name, e))?,
+            )),
+            None => None,
+        },
+        ValueKind::Binary => match row.try_get::<Option<String>, _>(name).map_err(err)? {
+            Some(s) => Some(SqlValue::Binary(
+                decode_hex(&s).map_err(|e| format!("Failed to decode column [{}] ({}): {}", column.name, column.data_type, e))?,
+            )),
+            None => None,
+        },
+    };
+
+    Ok(value.unwrap_or(SqlValue::Null))
+}
+
+/// Decodes every column of `row`, in the order of `columns`.
+pub fn decode_row(row: &MssqlRow, columns: &[&ColumnSpec]) -> Result<Vec<SqlValue>, String> {
+    columns.iter().map(|c| decode(row, c)).collect()
+}
+
+/// Binds the directly bound values of a row, matching the parameters of `value_exprs`.
+pub fn bind_row<'q>(
+    mut query: Query<'q, Mssql, MssqlArguments>,
+    columns: &[&ColumnSpec],
+    values: Vec<SqlValue>,
+) -> Query<'q, Mssql, MssqlArguments> {
+    for (column, value) in columns.iter().zip(values) {
+        if !column.lob {
+            query = bind(query, value);
+        }
+    }
+    query
+}
+
+/// Binds a value with its native sqlx type. Textual kinds are converted back by SQL Server
+/// (implicitly, or through the `CONVERT` in `value_exprs` for binary data).
+pub fn bind<'q>(query: Query<'q, Mssql, MssqlArguments>, value: SqlValue) -> Query<'q, Mssql, MssqlArguments> {
+    match value {
+        SqlValue::Null => query.bind(None::<String>),
+        SqlValue::Bool(v) => query.bind(v),
+        SqlValue::U8(v) => query.bind(v),
+        SqlValue::I16(v) => query.bind(v),
+        SqlValue::I32(v) => query.bind(v),
+        SqlValue::I64(v) => query.bind(v),
+        SqlValue::F32(v) => query.bind(v),
+        SqlValue::F64(v) => query.bind(v),
+        SqlValue::Decimal(v) | SqlValue::Text(v) | SqlValue::Temporal(v) => query.bind(v),
+        SqlValue::Guid(v) => query.bind(v.to_string()),
+        SqlValue::Binary(v) => query.bind(encode_hex(&v)),
+        // LOB values are staged on the replica connection, never bound directly
+        SqlValue::Lob(_) => query,
+    }
+}
+
+/// Text form of a primary key value for full load checkpoints; `key_from_text` reverses it.
+/// `None` for values that cannot be part of a key.
+pub fn key_to_text(value: &SqlValue) -> Option<String> {
+    let text = match value {
+        SqlValue::Bool(v) => (if *v { "1" } else { "0" }).to_string(),
+        SqlValue::U8(v) => v.to_string(),
+        SqlValue::I16(v) => v.to_string(),
+        SqlValue::I32(v) => v.to_string(),
+        SqlValue::I64(v) => v.to_string(),
+        SqlValue::F32(v) => v.to_string(),
+        SqlValue::F64(v) => v.to_string(),
+        SqlValue::Decimal(v) | SqlValue::Text(v) | SqlValue::Temporal(v) => v.clone(),
+        SqlValue::Guid(v) => v.to_string(),
+        SqlValue::Binary(v) => encode_hex(v),
+        SqlValue::Null | SqlValue::Lob(_) => return None,
+    };
+    Some(text)
+}
+
+/// Parses a value written by `key_to_text` back into the typed value of `column`.
+pub fn key_from_text(column: &ColumnSpec, text: &str) -> Result<SqlValue, String> {
+    let err = |e: String| format!("Invalid key value for column [{}] ({}): {}", column.name, column.data_type, e);
+    let value = match column.kind {
+        ValueKind::Bit => match text {
+            "1" => SqlValue::Bool(true),
+            "0" => SqlValue::Bool(false),
+            other => return Err(err(format!("'{}' is not 0 or 1", other))),
+        },
+        ValueKind::TinyInt => SqlValue::U8(text.parse().map_err(|e: std::num::ParseIntError| err(e.to_string()))?),
+        ValueKind::SmallInt => SqlValue::I16(text.parse().map_err(|e: std::num::ParseIntError| err(e.to_string()))?),
+        ValueKind::Int => SqlValue::I32(text.parse().map_err(|e: std::num::ParseIntError| err(e.to_string()))?),
+        ValueKind::BigInt => SqlValue::I64(text.parse().map_err(|e: std::num::ParseIntError| err(e.to_string()))?),
+        ValueKind::Real => SqlValue::F32(text.parse().map_err(|e: std::num::ParseFloatError| err(e.to_string()))?),
+        ValueKind::Float => SqlValue::F64(text.parse().map_err(|e: std::num::ParseFloatError| err(e.to_string()))?),
+        ValueKind::Decimal => SqlValue::Decimal(text.to_string()),
+        ValueKind::Text => SqlValue::Text(text.to_string()),
+        ValueKind::Temporal => SqlValue::Temporal(text.to_string()),
+        ValueKind::Guid => SqlValue::Guid(uuid::Uuid::parse_str(text).map_err(|e| err(e.to_string()))?),
+        ValueKind::Binary => SqlValue::Binary(decode_hex(text).map_err(err)?),
+    };
+    Ok(value)
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(format!("odd-length hex string ({} digits)", hex.len()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data_type: &str, value: SqlValue) -> SqlValue {
+        let column = ColumnSpec::new("Key", data_type, None).unwrap();
+        let text = key_to_text(&value).unwrap();
+        key_from_text(&column, &text).unwrap()
+    }
+
+    #[test]
+    fn key_text_round_trips_every_key_type() {
+        let cases = [
+            ("bit", SqlValue::Bool(true)),
+            ("tinyint", SqlValue::U8(255)),
+            ("smallint", SqlValue::I16(-32768)),
+            ("int", SqlValue::I32(-7)),
+            ("bigint", SqlValue::I64(9_007_199_254_740_993)),
+            ("real", SqlValue::F32(0.1)),
+            ("float", SqlValue::F64(1.0 / 3.0)),
+            ("decimal", SqlValue::Decimal("-12345.678900".to_string())),
+            ("nvarchar", SqlValue::Text("O'Brien, 東京".to_string())),
+            ("datetime2", SqlValue::Temporal("2024-03-01T10:15:42.1234567".to_string())),
+            ("uniqueidentifier", SqlValue::Guid(uuid::Uuid::parse_str("6f9619ff-8b86-d011-b42d-00c04fc964ff").unwrap())),
+            ("varbinary", SqlValue::Binary(vec![0x00, 0xFF, 0x10])),
+        ];
+        for (data_type, value) in cases {
+            assert_eq!(round_trip(data_type, value.clone()), value, "{}", data_type);
+        }
+    }
+
+    #[test]
+    fn null_and_lob_values_have_no_key_text() {
+        assert_eq!(key_to_text(&SqlValue::Null), None);
+        let lob = SqlValue::Lob(LobValue { binary: false, length: 0, pieces: Vec::new() });
+        assert_eq!(key_to_text(&lob), None);
+    }
+
+    #[test]
+    fn invalid_key_text_is_an_error() {
+        let cases = [("int", "12x"), ("bit", "true"), ("uniqueidentifier", "nope"), ("varbinary", "ABC")];
+        for (data_type, text) in cases {
+            let column = ColumnSpec::new("Key", data_type, None).unwrap();
+            assert!(key_from_text(&column, text).is_err(), "{} {}", data_type, text);
+        }
+    }
+}
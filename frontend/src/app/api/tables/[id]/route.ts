@@ -6,7 +6,8 @@ export async function POST(
     { params }: { params: Promise<{ id: string }> }
 ) {
     try {
-        const tableId = (await params).id;
+        // Table ids are schema-qualified ("[dbo].[User]"), URL-encoded by the dashboard
+        const tableId = decodeURIComponent((await params).id);
         const body = await request.json();
         const action = body.action;
 
@@ -27,9 +28,19 @@ export async function POST(
         } else if (action === 'trigger_full_load') {
             const currentKey = `mssql_sync:force_full_load:${tableId}`;
 
+            // A load triggered from the dashboard always starts from scratch rather than resuming
+            // the checkpoint of an interrupted one
+            await redis.set(`mssql_sync:full_load_restart:${tableId}`, 'true');
+
             // Set the force_full_load string to true (The Rust app acts on it and sets it to false when done)
             await redis.set(currentKey, 'true');
 
+            return NextResponse.json({ success: true, table: tableId, action, newState: true });
+
+        } else if (action === 'reseed_identity') {
+            // The Rust app aligns the Replica's identity with the Primary on its next pass and clears the key
+            await redis.set(`mssql_sync:reseed_identity:${tableId}`, 'true');
+
             return NextResponse.json({ success: true, table: tableId, action, newState: true });
         }
 
@@ -1,17 +1,28 @@
 import { NextResponse } from 'next/server';
 import redis from '@/lib/redis';
 
+const ENABLED_PREFIX = 'mssql_sync:enabled:';
+
+// Table ids are the "[schema].[name]" form of the Redis keys, with "]" doubled inside a name
+function parseTableId(id: string): { schema: string; name: string } {
+    const match = /^\[((?:[^\]]|\]\])*)\]\.\[((?:[^\]]|\]\])*)\]$/.exec(id);
+    if (!match) {
+        return { schema: 'dbo', name: id };
+    }
+    return { schema: match[1].replace(/\]\]/g, ']'), name: match[2].replace(/\]\]/g, ']') };
+}
+
 export async function GET() {
     try {
         // Find all tracked tables by checking the enabled keys
-        const keys = await redis.keys('mssql_sync:enabled:*');
+        const keys = await redis.keys(`${ENABLED_PREFIX}*`);
 
         if (keys.length === 0) {
             return NextResponse.json({ tables: [] });
         }
 
-        // Extract table names from keys (e.g. mssql_sync:enabled:User -> User)
-        const tableNames = keys.map((key) => key.split(':').pop() || '');
+        // Extract table ids from keys (e.g. mssql_sync:enabled:[dbo].[User] -> [dbo].[User])
+        const tableNames = keys.map((key) => key.slice(ENABLED_PREFIX.length));
 
         // Fetch the enabled, force_full_load, version, and progress states for all tables in a single pipeline
         const pipeline = redis.pipeline();
@@ -20,6 +31,7 @@ export async function GET() {
             pipeline.get(`mssql_sync:force_full_load:${table}`);
             pipeline.get(`mssql_sync:version:${table}`);
             pipeline.get(`mssql_sync:progress:${table}`);
+            pipeline.get(`mssql_sync:needs_reload:${table}`);
         });
 
         const results = await pipeline.exec();
@@ -30,11 +42,12 @@ export async function GET() {
 
         const tables = tableNames.map((tableName, index) => {
             // Results are returned as [error, value] arrays
-            const baseIdx = index * 4;
+            const baseIdx = index * 5;
             const enabledVal = results[baseIdx][1] as string | null;
             const forceLoadVal = results[baseIdx + 1][1] as string | null;
             const versionVal = results[baseIdx + 2][1] as string | null;
             const progressVal = results[baseIdx + 3][1] as string | null;
+            const needsReloadVal = results[baseIdx + 4][1] as string | null;
 
             let progress = null;
             if (progressVal) {
@@ -45,18 +58,31 @@ export async function GET() {
                 }
             }
 
+            let needsReload = null;
+            if (needsReloadVal) {
+                try {
+                    needsReload = JSON.parse(needsReloadVal);
+                } catch (e) {
+                    console.error(`Failed to parse needs_reload for ${tableName}:`, e);
+                }
+            }
+
+            const { schema, name } = parseTableId(tableName);
+
             return {
                 id: tableName,
-                name: tableName,
+                schema,
+                name,
                 enabled: enabledVal === 'true',
                 forceFullLoad: forceLoadVal === 'true',
                 version: versionVal ? parseInt(versionVal, 10) : 0,
                 progress,
+                needsReload,
             };
         });
 
-        // Sort alphabetically by table name
-        tables.sort((a, b) => a.name.localeCompare(b.name));
+        // Sort alphabetically by schema, then table name
+        tables.sort((a, b) => a.schema.localeCompare(b.schema) || a.name.localeCompare(b.name));
 
         return NextResponse.json({ tables });
     } catch (error) {
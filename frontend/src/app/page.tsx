@@ -7,11 +7,13 @@ import { Database, RefreshCw, AlertCircle, PlaySquare, ToggleLeft, ToggleRight,
 
 interface TableSyncState {
   id: string;
+  schema: string;
   name: string;
   enabled: boolean;
   forceFullLoad: boolean;
   version: number;
   progress: { synced: number; total: number; startedAt?: number; updatedAt?: number } | null;
+  needsReload: { reason: string; lastVersion: number; minValidVersion: number; detectedAt: number } | null;
 }
 
 interface AppConfig {
@@ -73,7 +75,7 @@ export default function Dashboard() {
           // Clean up the ref
           delete loadStartTimesRef.current[t.id];
 
-          toast.success(`Full load completed for ${t.name}${timeMsg}!`, {
+          toast.success(`Full load completed for ${t.id}${timeMsg}!`, {
             duration: 5000,
             position: 'top-right',
             style: {
@@ -126,7 +128,7 @@ export default function Dashboard() {
   const toggleEnabled = async (tableId: string) => {
     setActingOn(tableId);
     try {
-      const res = await fetch(`/api/tables/${tableId}`, {
+      const res = await fetch(`/api/tables/${encodeURIComponent(tableId)}`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ action: "toggle_enabled" })
@@ -147,7 +149,7 @@ export default function Dashboard() {
     setConfirmTable(null); // Close modal
     setActingOn(tableId + "_full");
     try {
-      const res = await fetch(`/api/tables/${tableId}`, {
+      const res = await fetch(`/api/tables/${encodeURIComponent(tableId)}`, {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ action: "trigger_full_load" })
@@ -272,7 +274,7 @@ export default function Dashboard() {
                       <Database className="w-4 h-4" />
                     </div>
                     <div className="min-w-0">
-                      <h3 className="text-sm font-medium text-white truncate">{table.name}</h3>
+                      <h3 className="text-sm font-medium text-white truncate"><span className="text-neutral-500">{table.schema}.</span>{table.name}</h3>
                       <p className="text-xs text-neutral-500 font-mono mt-0.5 truncate hidden md:block">mssql_sync:enabled:{table.id}</p>
                     </div>
                   </div>
@@ -308,6 +310,13 @@ export default function Dashboard() {
                       )}
                     </button>
 
+                    {table.needsReload && !table.forceFullLoad && (
+                      <div className="mt-1 inline-flex items-center space-x-1 text-[10px] text-amber-400" title={table.needsReload.reason}>
+                        <AlertCircle className="w-3 h-3" />
+                        <span>Needs full reload</span>
+                      </div>
+                    )}
+
                     {table.enabled && table.progress && (
                       <div className="mt-1 w-full max-w-[160px] mx-auto text-center" title={`${table.progress.synced.toLocaleString()} / ${table.progress.total.toLocaleString()} rows`}>
                         <div className="flex justify-between text-[10px] text-neutral-500 font-mono mb-1">
@@ -4,6 +4,10 @@ GO
 ALTER DATABASE testct SET ENABLE_BROKER WITH ROLLBACK IMMEDIATE;
 GO
 
+-- Enable SNAPSHOT isolation (consistent version + row reads during sync)
+ALTER DATABASE testct SET ALLOW_SNAPSHOT_ISOLATION ON;
+GO
+
 USE testct;
 GO
 